@@ -5,9 +5,9 @@ use pyo3::prelude::*;
 use pyo3::types::IntoPyDict;
 
 // const FILTER :&[&'static str] = &[];
-const FILTER: &[&'static str] = &["roundtrip", "escaping"];
+const FILTER: &[&str] = &["roundtrip", "escaping"];
 // const FILTER :&[&'static str] = &["bad_value_9"];
-// const FILTER: &[&'static str] = &["empty_metadata"];
+// const FILTER: &[&str] = &["empty_metadata"];
 
 fn enumerate_test_cases() -> Vec<DirEntry> {
     let test_dir: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/../parse-tests");
@@ -17,13 +17,13 @@ fn enumerate_test_cases() -> Vec<DirEntry> {
         .unwrap()
         .map(|x| x.unwrap())
         .collect();
-    dirs.sort_by(|b, a| a.path().cmp(&b.path()));
+    dirs.sort_by_key(|b| std::cmp::Reverse(b.path()));
     dirs.into_iter()
         .filter(|x| FILTER.is_empty() || FILTER.contains(&x.file_name().to_str().unwrap()))
         .collect()
 }
 
-const PYTHON_CODE: &'static str = include_str!("../scripts/py-reference-parser.py");
+const PYTHON_CODE: &str = include_str!("../scripts/py-reference-parser.py");
 
 #[inline]
 fn do_test<A>(group: &mut criterion::BenchmarkGroup<A>, path: DirEntry)
@@ -45,7 +45,7 @@ where
 
         group.bench_function(BenchmarkId::new(&test_name, "python"), |b| {
             b.iter(|| {
-                py.run(PYTHON_CODE, None, Some(&locals))
+                py.run(PYTHON_CODE, None, Some(locals))
                     .expect("python error")
             })
         });
@@ -54,23 +54,39 @@ where
     });
 
     group.bench_function(BenchmarkId::new(&test_name, "om-nomnomnom"), |b| {
-        b.iter(|| match om_nomnomnom::parse(&test_data) {
-            Ok(data) => {
+        b.iter(|| {
+            if let Ok(data) = om_nomnomnom::parse(&test_data) {
                 data.values().count();
             }
-            Err(_) => {}
         })
     });
 
+    group.bench_function(BenchmarkId::new(&test_name, "om-nomnomnom-bytes"), |b| {
+        b.iter(|| {
+            if let Ok(data) = om_nomnomnom::parse_bytes(test_data.as_bytes()) {
+                data.iter().count();
+            }
+        })
+    });
+
+    // Lexing alone, this allocates nothing but the errors
+    group.bench_function(
+        BenchmarkId::new(&test_name, "om-nomnomnom-lexer-bytes"),
+        |b| {
+            b.iter(|| {
+                om_nomnomnom::lexer::bytes::lines(test_data.as_bytes())
+                    .filter(Result::is_ok)
+                    .count()
+            })
+        },
+    );
+
     group.bench_function(BenchmarkId::new(&test_name, "openmetrics-parser"), |b| {
-        b.iter(
-            || match openmetrics_parser::openmetrics::parse_openmetrics(&test_data) {
-                Ok(data) => {
-                    data.families.values().count();
-                }
-                Err(_) => {}
-            },
-        )
+        b.iter(|| {
+            if let Ok(data) = openmetrics_parser::openmetrics::parse_openmetrics(&test_data) {
+                data.families.values().count();
+            }
+        })
     });
 }
 
@@ -0,0 +1,46 @@
+//! Generates the `parse_tests!` macro, which runs `open_metrics_test!` on each case in
+//! `parse-tests`, see `src/test.rs`.
+
+use std::{env, fs, io::Write, path::Path};
+
+/// Cases the parser doesn't pass yet, these are generated as ignored tests
+const IGNORED: &[&str] = &[];
+
+fn main() {
+    let tests = Path::new(env!("CARGO_MANIFEST_DIR")).join("../parse-tests");
+    println!("cargo:rerun-if-changed={}", tests.display());
+
+    // The cases aren't shipped with the crate, so there may be nothing to generate
+    let mut cases: Vec<String> = fs::read_dir(&tests)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.path().join("test.json").is_file())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| {
+                    name.starts_with(|c: char| c.is_ascii_lowercase())
+                        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    cases.sort();
+
+    let out =
+        Path::new(&env::var("OUT_DIR").expect("OUT_DIR is set by cargo")).join("parse_tests.rs");
+    let mut out = fs::File::create(out).expect("couldn't create parse_tests.rs");
+    let mut tests = String::new();
+    for case in cases {
+        match IGNORED.contains(&case.as_str()) {
+            true => tests.push_str(&format!("open_metrics_test!($check, {}, ignore);\n", case)),
+            false => tests.push_str(&format!("open_metrics_test!($check, {});\n", case)),
+        }
+    }
+
+    write!(
+        out,
+        "macro_rules! parse_tests {{\n    ($check:ident) => {{\n{}    }};\n}}\n",
+        tests
+    )
+    .expect("couldn't write parse_tests.rs");
+}
@@ -1,9 +1,9 @@
 use anyhow::{anyhow, Result};
 use indoc::indoc;
 use itertools::Itertools;
-use om_nomnomnom::parser::{MetricType, SampleKind};
+use om_nomnomnom::parser::MetricType;
 
-const COLORS: &[&'static str] = &[
+const COLORS: &[&str] = &[
     "\u{2591}", "\u{2592}", "\u{2593}",
     "\u{25A3}", "\u{25A9}", "\u{25A4}"
 ];
@@ -24,7 +24,7 @@ fn main() -> Result<()> {
 
     let progname = args.next().ok_or(anyhow!("ARGV[0] was not set??"))?;
 
-    while let Some(arg) = args.next() {
+    for arg in args {
         match arg.as_str() {
             "--help" => {
                 println!("Usage: {} [--print-exposition]", progname);
@@ -37,26 +37,21 @@ fn main() -> Result<()> {
         }
     }
 
-    let families = om_nomnomnom::parse(&om_data)?;
+    let families = om_nomnomnom::parse(om_data)?;
 
     let (family_name, histogram) = families.iter().next().ok_or(anyhow!("empty exposition?"))?;
 
     assert_eq!(MetricType::Histogram, histogram.metric_type);
 
-    let sample_count = histogram
-        .samples
-        .iter()
-        .filter(|sample| sample.name.ends_with("_count"))
+    let histogram = histogram
+        .as_histograms()
+        .ok_or(anyhow!("not a histogram?"))?
+        .into_iter()
         .next()
-        .ok_or(anyhow!("no _count?"))?
-        .number
-        .round() as usize;
+        .ok_or(anyhow!("no samples?"))?;
 
-    let buckets = histogram
-        .samples
-        .iter()
-        .filter(|sample| matches! {sample.kind, SampleKind::HistogramBucket(_)})
-        .collect_vec();
+    let sample_count = histogram.count.ok_or(anyhow!("no _count?"))?.as_f64().round() as usize;
+    let buckets = histogram.buckets;
 
     let factor = match sample_count {
         sample_count if sample_count < 25 => 2,
@@ -65,7 +60,7 @@ fn main() -> Result<()> {
 
     print!("Distribution of «{}»: ", family_name);
     buckets.iter().zip(COLORS).fold(0, |acc, (bucket, color)| {
-        let cur_length = bucket.number.round() as usize;
+        let cur_length = bucket.count.as_f64().round() as usize;
         print!("{}", color.repeat((cur_length - acc) * factor));
         cur_length
     });
@@ -76,7 +71,7 @@ fn main() -> Result<()> {
         buckets
             .iter()
             .zip(COLORS.iter().cycle())
-            .map(|(bucket, color)| format!("{} ≤ {} ", color, bucket.labels["le"]))
+            .map(|(bucket, color)| format!("{} ≤ {} ", color, bucket.upper_bound))
             .join(" ")
     );
 
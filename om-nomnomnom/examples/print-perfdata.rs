@@ -12,7 +12,7 @@ impl<'a> ToPerfdata for MetricFamily<'a> {
             Err(anyhow!("ToPerdata only supports Gauge types"))?
         }
 
-        let unit = self.unit.ok_or(anyhow!("no unit"))?;
+        let unit = self.unit.as_ref().ok_or(anyhow!("no unit"))?;
         let measurement = self.samples.first().ok_or(anyhow!("no samples?"))?;
 
         Ok(format!(
@@ -39,7 +39,7 @@ fn main() -> Result<()> {
 
     let progname = args.next().ok_or(anyhow!("ARGV[0] was not set??"))?;
 
-    while let Some(arg) = args.next() {
+    for arg in args {
         match arg.as_str() {
             "--help" => {
                 println!("Usage: {} [--print-exposition]", progname);
@@ -52,7 +52,7 @@ fn main() -> Result<()> {
         }
     }
 
-    let families = om_nomnomnom::parse(&om_data)?;
+    let families = om_nomnomnom::parse(om_data)?;
 
     let (_family_name, gauge) = families.iter().next().ok_or(anyhow!("empty exposition?"))?;
 
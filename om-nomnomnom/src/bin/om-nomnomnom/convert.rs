@@ -0,0 +1,148 @@
+//! `om-nomnomnom convert`: rewrites an exposition in another format.
+
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+};
+
+use anyhow::{bail, Context, Result};
+
+use om_nomnomnom::{
+    parser::{MetricSet, MetricType},
+    Format,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// The exposition to convert, `-` for stdin
+    #[clap(default_value = "-")]
+    input: String,
+
+    /// Where to write the result, `-` for stdout
+    #[clap(short, long, default_value = "-")]
+    output: String,
+
+    /// The format of the input
+    #[clap(long, value_enum, default_value = "auto")]
+    from: Input,
+
+    /// The format to convert to
+    #[clap(long, value_enum)]
+    to: Output,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Input {
+    /// OpenMetrics if the input ends with `# EOF` or uses syntax only OpenMetrics has, Prometheus
+    /// otherwise
+    Auto,
+    Openmetrics,
+    /// The Prometheus text format, version 0.0.4
+    Prometheus,
+    /// The families as written by `--to json`
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Output {
+    /// The families, keyed by name
+    Json,
+    /// One sample per line, along with the name and type of its family
+    Ndjson,
+    Openmetrics,
+    /// The Prometheus text format, version 0.0.4
+    Prometheus,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let mut data = String::new();
+    match args.input.as_str() {
+        "-" => {
+            std::io::stdin()
+                .read_to_string(&mut data)
+                .context("couldn't read stdin")?;
+        }
+        path => {
+            data =
+                std::fs::read_to_string(path).with_context(|| format!("couldn't read {}", path))?;
+        }
+    }
+
+    let out = convert(&data, args.from, args.to)?;
+
+    match args.output.as_str() {
+        "-" => std::io::stdout().write_all(out.as_bytes())?,
+        path => std::fs::write(path, out).with_context(|| format!("couldn't write {}", path))?,
+    }
+
+    Ok(())
+}
+
+/// Rewrites an exposition, or families in JSON, in the format asked for
+pub(crate) fn convert(data: &str, from: Input, to: Output) -> Result<String> {
+    let from = match from {
+        Input::Auto => match Format::detect(data) {
+            Format::Prometheus => Input::Prometheus,
+            _ => Input::Openmetrics,
+        },
+        from => from,
+    };
+
+    let set = match from {
+        Input::Json => serde_json::from_str(data).context("couldn't read the families")?,
+        Input::Prometheus => om_nomnomnom::parse_prometheus(data)?,
+        _ => om_nomnomnom::parse_with_format(data, Format::OpenMetrics)?,
+    };
+
+    let set = match (from, to) {
+        (Input::Prometheus, Output::Openmetrics) => openmetrics_counters(set)?,
+        _ => set,
+    };
+
+    Ok(match to {
+        Output::Json => serde_json::to_string_pretty(&set)? + "\n",
+        Output::Ndjson => ndjson(&set)?,
+        Output::Openmetrics => om_nomnomnom::serialize_metric_set(&set),
+        Output::Prometheus => om_nomnomnom::serialize_prometheus(&set),
+    })
+}
+
+/// The serializer writes a Prometheus counter `foo_total` as the OpenMetrics counter `foo`.  Fails
+/// if that would clash with another family's name, as keeping the suffix would make for a family
+/// OpenMetrics can't express.
+pub(crate) fn openmetrics_counters(set: MetricSet) -> Result<MetricSet> {
+    let taken: HashSet<&str> = set.names().collect();
+
+    for (name, family) in set.iter() {
+        if family.metric_type == MetricType::Counter {
+            if let Some(base) = name
+                .strip_suffix("_total")
+                .filter(|base| taken.contains(base))
+            {
+                bail!(
+                    "counter «{}» can't be renamed «{}», another family's name",
+                    name,
+                    base
+                );
+            }
+        }
+    }
+
+    Ok(set)
+}
+
+fn ndjson(set: &MetricSet) -> Result<String> {
+    let mut out = String::new();
+
+    for (name, family) in set.iter() {
+        for sample in family.samples.iter() {
+            let mut line = serde_json::to_value(sample)?;
+            line["family"] = name.into();
+            line["type"] = serde_json::to_value(&family.metric_type)?;
+            out.push_str(&serde_json::to_string(&line)?);
+            out.push('\n');
+        }
+    }
+
+    Ok(out)
+}
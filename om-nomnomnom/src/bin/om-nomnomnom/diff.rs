@@ -0,0 +1,365 @@
+//! `om-nomnomnom diff`: compares two expositions family by family.
+
+use std::{collections::HashMap, io::Read};
+
+use anyhow::{bail, Context, Result};
+use serde_derive::Serialize;
+
+use om_nomnomnom::{
+    parser::{LabelSet, MetricFamily, MetricNumber, MetricPoint, MetricSet, Sample, Series},
+    Format,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// The exposition to compare against, `-` for stdin
+    old: String,
+
+    /// The exposition to compare, `-` for stdin unless the other one is
+    new: String,
+
+    /// How to report the differences
+    #[clap(long, value_enum, default_value = "text")]
+    format: Output,
+
+    /// auto, openmetrics, or prometheus
+    #[clap(long, default_value = "auto")]
+    input_format: Format,
+
+    /// Only compare families and their series, not the values of the samples
+    #[clap(long)]
+    schema_only: bool,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum Output {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Diff {
+    added: Vec<Family>,
+    removed: Vec<Family>,
+    changed: Vec<FamilyDiff>,
+}
+
+#[derive(Debug, Serialize)]
+struct Family {
+    name: String,
+    #[serde(rename = "type")]
+    metric_type: &'static str,
+}
+
+/// How a family present in both expositions changed
+#[derive(Debug, Serialize)]
+pub(crate) struct FamilyDiff {
+    name: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    metric_type: Option<Change<&'static str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    help: Option<Change<Option<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<Change<Option<String>>>,
+    /// Series only in the new exposition
+    added: Vec<String>,
+    /// Series only in the old exposition
+    removed: Vec<String>,
+    values: Vec<ValueChange>,
+}
+
+#[derive(Debug, Serialize)]
+struct Change<T> {
+    old: T,
+    new: T,
+}
+
+#[derive(Debug, Serialize)]
+struct ValueChange {
+    series: String,
+    old: MetricNumber,
+    new: MetricNumber,
+    delta: MetricNumber,
+}
+
+/// Compares the two expositions and reports how they differ on stdout, returning whether they
+/// do.
+pub fn run(args: Args) -> Result<bool> {
+    if args.old == "-" && args.new == "-" {
+        bail!("only one of the expositions can be read from stdin");
+    }
+
+    let (old, new) = (read(&args.old)?, read(&args.new)?);
+    let old = om_nomnomnom::parse_with_format(&old, args.input_format)
+        .with_context(|| format!("couldn't parse {}", args.old))?;
+    let new = om_nomnomnom::parse_with_format(&new, args.input_format)
+        .with_context(|| format!("couldn't parse {}", args.new))?;
+
+    let diff = diff(&old, &new, args.schema_only);
+
+    match args.format {
+        Output::Text => print!("{}", text(&diff)),
+        Output::Json => println!("{}", serde_json::to_string_pretty(&diff)?),
+    }
+
+    Ok(!diff.added.is_empty() || !diff.removed.is_empty() || !diff.changed.is_empty())
+}
+
+fn read(file: &str) -> Result<String> {
+    match file {
+        "-" => {
+            let mut data = String::new();
+            std::io::stdin()
+                .read_to_string(&mut data)
+                .context("couldn't read stdin")?;
+            Ok(data)
+        }
+        path => std::fs::read_to_string(path).with_context(|| format!("couldn't read {}", path)),
+    }
+}
+
+pub(crate) fn diff(old: &MetricSet, new: &MetricSet, schema_only: bool) -> Diff {
+    let family = |(name, family): (&str, &MetricFamily)| Family {
+        name: name.to_string(),
+        metric_type: family.metric_type.as_str(),
+    };
+
+    Diff {
+        added: new
+            .iter()
+            .filter(|(name, _)| !old.contains_key(name))
+            .map(family)
+            .collect(),
+        removed: old
+            .iter()
+            .filter(|(name, _)| !new.contains_key(name))
+            .map(family)
+            .collect(),
+        changed: new
+            .iter()
+            .filter_map(|(name, new)| {
+                let old = old.get(name)?;
+                diff_family(name, old, new, schema_only)
+            })
+            .collect(),
+    }
+}
+
+/// Compares a family found in both expositions, `None` if it didn't change.
+///
+/// Series are told apart as in [`MetricFamily::series`], so the buckets of a histogram are
+/// compared within their series rather than each being a series of its own.
+pub(crate) fn diff_family(
+    name: &str,
+    old: &MetricFamily,
+    new: &MetricFamily,
+    schema_only: bool,
+) -> Option<FamilyDiff> {
+    let change = |old: Option<&str>, new: Option<&str>| {
+        (old != new).then(|| Change {
+            old: old.map(str::to_string),
+            new: new.map(str::to_string),
+        })
+    };
+
+    let mut diff = FamilyDiff {
+        name: name.to_string(),
+        metric_type: (old.metric_type != new.metric_type).then(|| Change {
+            old: old.metric_type.as_str(),
+            new: new.metric_type.as_str(),
+        }),
+        help: change(old.help.as_deref(), new.help.as_deref()),
+        unit: change(old.unit.as_deref(), new.unit.as_deref()),
+        added: vec![],
+        removed: vec![],
+        values: vec![],
+    };
+
+    let (old_series, new_series) = (old.series(), new.series());
+    let (old_index, new_index) = (by_labels(&old_series), by_labels(&new_series));
+
+    for series in new_series.iter() {
+        match old_index.get(&series.labels) {
+            None => diff.added.push(series_name(name, &series.labels)),
+            Some(old) => diff_series(&mut diff, old, series, schema_only),
+        }
+    }
+
+    for series in old_series.iter() {
+        if !new_index.contains_key(&series.labels) {
+            diff.removed.push(series_name(name, &series.labels));
+        }
+    }
+
+    let unchanged = diff.metric_type.is_none()
+        && diff.help.is_none()
+        && diff.unit.is_none()
+        && diff.added.is_empty()
+        && diff.removed.is_empty()
+        && diff.values.is_empty();
+
+    (!unchanged).then_some(diff)
+}
+
+/// Compares a series found on both sides.  If it has a single point on each, as when comparing two
+/// scrapes, they're compared whatever their timestamps.  Otherwise points are paired by timestamp,
+/// and those found on one side only are added or removed, e.g. `a{b="c"} @ 123`.
+fn diff_series(diff: &mut FamilyDiff, old: &Series, new: &Series, schema_only: bool) {
+    let (old_points, new_points) = match (old.points.as_slice(), new.points.as_slice()) {
+        ([old], [new]) => return diff_points(diff, old, new, None, schema_only),
+        points => points,
+    };
+    let find = |points: &'_ [MetricPoint], timestamp: Option<f64>| {
+        let timestamp = timestamp.map(f64::to_bits);
+        points
+            .iter()
+            .position(|point| point.timestamp.map(f64::to_bits) == timestamp)
+    };
+
+    for new in new_points.iter() {
+        match find(old_points, new.timestamp) {
+            None => diff
+                .added
+                .push(point_name(&diff.name, &new.labels, new.timestamp)),
+            Some(i) => diff_points(diff, &old_points[i], new, new.timestamp, schema_only),
+        }
+    }
+
+    for old in old_points.iter() {
+        if find(new_points, old.timestamp).is_none() {
+            diff.removed
+                .push(point_name(&diff.name, &old.labels, old.timestamp));
+        }
+    }
+}
+
+/// Compares two points sample by sample, e.g. bucket by bucket.  Samples found on one side only
+/// are added or removed as if they were series.  `timestamp` is the one the points were paired
+/// by, if they were.
+fn diff_points(
+    diff: &mut FamilyDiff,
+    old: &MetricPoint,
+    new: &MetricPoint,
+    timestamp: Option<f64>,
+    schema_only: bool,
+) {
+    let (old_samples, new_samples) = (samples(old), samples(new));
+
+    for sample in new.samples.iter() {
+        let name = || point_name(&sample.name, &sample.labels, timestamp);
+        match old_samples.get(&(sample.name.as_ref(), &sample.labels)) {
+            None => diff.added.push(name()),
+            Some(old) if !schema_only && !same_number(&old.number, &sample.number) => {
+                diff.values.push(ValueChange {
+                    series: name(),
+                    old: old.number,
+                    new: sample.number,
+                    delta: sample.number - old.number,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+
+    for sample in old.samples.iter() {
+        if !new_samples.contains_key(&(sample.name.as_ref(), &sample.labels)) {
+            diff.removed
+                .push(point_name(&sample.name, &sample.labels, timestamp));
+        }
+    }
+}
+
+/// The series of a family by their labels
+fn by_labels<'s, 'f, 'a>(
+    series: &'s [Series<'f, 'a>],
+) -> HashMap<&'s LabelSet<'a>, &'s Series<'f, 'a>> {
+    series
+        .iter()
+        .map(|series| (&series.labels, series))
+        .collect()
+}
+
+type SampleKey<'f, 'a> = (&'f str, &'f LabelSet<'a>);
+
+/// The samples of a point by name and labels
+fn samples<'f, 'a>(point: &MetricPoint<'f, 'a>) -> HashMap<SampleKey<'f, 'a>, &'f Sample<'a>> {
+    point
+        .samples
+        .iter()
+        .map(|sample| ((sample.name.as_ref(), &sample.labels), *sample))
+        .collect()
+}
+
+fn series_name(name: &str, labels: &LabelSet) -> String {
+    match labels.is_empty() {
+        true => name.to_string(),
+        false => format!("{}{}", name, labels),
+    }
+}
+
+fn point_name(name: &str, labels: &LabelSet, timestamp: Option<f64>) -> String {
+    match timestamp {
+        Some(timestamp) => format!("{} @ {}", series_name(name, labels), timestamp),
+        None => series_name(name, labels),
+    }
+}
+
+/// Values are compared numerically, and a NaN is the same as another
+fn same_number(old: &MetricNumber, new: &MetricNumber) -> bool {
+    old == new || (old.is_nan() && new.is_nan())
+}
+
+pub(crate) fn text(diff: &Diff) -> String {
+    let mut out = String::new();
+
+    for family in diff.added.iter() {
+        out.push_str(&format!("+ {} ({})\n", family.name, family.metric_type));
+    }
+    for family in diff.removed.iter() {
+        out.push_str(&format!("- {} ({})\n", family.name, family.metric_type));
+    }
+
+    let optional = |s: &Option<String>| match s {
+        Some(s) => format!("{:?}", s),
+        None => "none".to_string(),
+    };
+    for family in diff.changed.iter() {
+        out.push_str(&format!("~ {}\n", family.name));
+        if let Some(Change { old, new }) = &family.metric_type {
+            out.push_str(&format!("    type: {} -> {}\n", old, new));
+        }
+        if let Some(Change { old, new }) = &family.help {
+            out.push_str(&format!(
+                "    help: {} -> {}\n",
+                optional(old),
+                optional(new)
+            ));
+        }
+        if let Some(Change { old, new }) = &family.unit {
+            out.push_str(&format!(
+                "    unit: {} -> {}\n",
+                optional(old),
+                optional(new)
+            ));
+        }
+        for series in family.added.iter() {
+            out.push_str(&format!("    + {}\n", series));
+        }
+        for series in family.removed.iter() {
+            out.push_str(&format!("    - {}\n", series));
+        }
+        for value in family.values.iter() {
+            // Display ignores the sign flag, so a positive delta gets its `+` here
+            let sign = match value.delta > 0. {
+                true => "+",
+                false => "",
+            };
+            out.push_str(&format!(
+                "    {}: {} -> {} ({}{})\n",
+                value.series, value.old, value.new, sign, value.delta
+            ));
+        }
+    }
+
+    out
+}
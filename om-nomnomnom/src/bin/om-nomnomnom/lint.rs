@@ -0,0 +1,198 @@
+//! `om-nomnomnom lint`: validates expositions and reports every problem found, see
+//! [`om_nomnomnom::parse_lenient`].
+
+use std::io::Read;
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+
+use om_nomnomnom::{parser::ParserOptions, Format, OmError};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// The expositions to check, `-` or none at all for stdin
+    files: Vec<String>,
+
+    /// How to report problems
+    #[clap(long, value_enum, default_value = "text")]
+    format: Output,
+
+    /// auto, openmetrics, or prometheus
+    #[clap(long, default_value = "openmetrics")]
+    input_format: Format,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum Output {
+    /// One `file:line:column: rule: message` line per problem
+    Text,
+    /// An array of problems
+    Json,
+    /// A SARIF 2.1.0 log, for code scanning tools
+    Sarif,
+}
+
+/// A problem found in one of the files
+pub(crate) struct Problem {
+    pub(crate) file: String,
+    pub(crate) error: OmError,
+}
+
+/// Checks each file and reports what's wrong with them on stdout, returning how many problems
+/// were found.
+pub fn run(args: Args) -> Result<usize> {
+    let files = match args.files.is_empty() {
+        true => vec!["-".to_string()],
+        false => args.files,
+    };
+    let options = ParserOptions::new().format(args.input_format);
+
+    let mut problems = vec![];
+    for file in files {
+        let data = read(&file)?;
+        let name = match file.as_str() {
+            "-" => "<stdin>".to_string(),
+            _ => file,
+        };
+
+        problems.extend(check(&data, &options).into_iter().map(|error| Problem {
+            file: name.clone(),
+            error,
+        }));
+    }
+
+    match args.format {
+        Output::Text => {
+            for problem in problems.iter() {
+                println!("{}", text(problem));
+            }
+        }
+        Output::Json => {
+            let problems: Vec<Value> = problems.iter().map(json).collect();
+            println!("{}", serde_json::to_string_pretty(&problems)?);
+        }
+        Output::Sarif => println!("{}", serde_json::to_string_pretty(&sarif(&problems))?),
+    }
+
+    Ok(problems.len())
+}
+
+fn read(file: &str) -> Result<Vec<u8>> {
+    match file {
+        "-" => {
+            let mut data = vec![];
+            std::io::stdin()
+                .read_to_end(&mut data)
+                .context("couldn't read stdin")?;
+            Ok(data)
+        }
+        path => std::fs::read(path).with_context(|| format!("couldn't read {}", path)),
+    }
+}
+
+pub(crate) fn check(data: &[u8], options: &ParserOptions) -> Vec<OmError> {
+    match std::str::from_utf8(data) {
+        Ok(data) => om_nomnomnom::parse_lenient(data, options).1,
+        // Only the lexer takes bytes, so the lines of an exposition that isn't UTF-8 are checked
+        // but not its families
+        Err(_) => om_nomnomnom::lexer::bytes::lines(data)
+            .filter_map(Result::err)
+            .collect(),
+    }
+}
+
+pub(crate) fn text(problem: &Problem) -> String {
+    let Problem { file, error } = problem;
+    let mut text = match error.location() {
+        Some(location) => format!("{}:{}:{}: ", file, location.line, location.column),
+        None => format!("{}: ", file),
+    };
+    text.push_str(&format!("{}: {}", error.rule_id(), error.message()));
+
+    if let Some(family) = error
+        .location()
+        .and_then(|location| location.family.as_ref())
+    {
+        text.push_str(&format!(" in «{}»", family));
+    }
+
+    text
+}
+
+pub(crate) fn json(problem: &Problem) -> Value {
+    let location = problem.error.location();
+
+    json!({
+        "file": problem.file,
+        "line": location.map(|location| location.line),
+        "column": location.map(|location| location.column),
+        "rule": problem.error.rule_id(),
+        "message": problem.error.message(),
+        "family": location.and_then(|location| location.family.as_ref()),
+        "text": location.map(|location| &location.text),
+    })
+}
+
+/// See the [SARIF 2.1.0 specification](https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html)
+pub(crate) fn sarif(problems: &[Problem]) -> Value {
+    // Each rule broken is described once, the first error breaking it serves as its description
+    let mut rules: Vec<&Problem> = vec![];
+    for problem in problems.iter() {
+        if !rules
+            .iter()
+            .any(|rule| rule.error.rule_id() == problem.error.rule_id())
+        {
+            rules.push(problem);
+        }
+    }
+
+    let rules: Vec<Value> = rules
+        .iter()
+        .map(|rule| {
+            let description = match &rule.error {
+                OmError::LexError { .. } => "the line doesn't follow the exposition format".into(),
+                error => error.message(),
+            };
+            json!({
+                "id": rule.error.rule_id(),
+                "shortDescription": { "text": description },
+            })
+        })
+        .collect();
+
+    let results: Vec<Value> = problems
+        .iter()
+        .map(|problem| {
+            let mut physical = json!({ "artifactLocation": { "uri": problem.file } });
+            if let Some(location) = problem.error.location() {
+                physical["region"] = json!({
+                    "startLine": location.line,
+                    "startColumn": location.column,
+                    "snippet": { "text": location.text },
+                });
+            }
+
+            json!({
+                "ruleId": problem.error.rule_id(),
+                "level": "error",
+                "message": { "text": problem.error.message() },
+                "locations": [{ "physicalLocation": physical }],
+            })
+        })
+        .collect();
+
+    json!({
+        "version": "2.1.0",
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "om-nomnomnom",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
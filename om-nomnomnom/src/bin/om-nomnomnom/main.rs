@@ -0,0 +1,109 @@
+use std::borrow::Borrow;
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+
+mod convert;
+mod diff;
+mod lint;
+mod query;
+
+#[cfg(test)]
+mod test;
+
+#[derive(Debug, Parser)]
+#[clap(
+    version,
+    args_conflicts_with_subcommands = true,
+    arg_required_else_help = true
+)]
+struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Prints the families of this exposition, same as `print`
+    #[clap(short, long)]
+    input: Option<String>,
+
+    /// auto, openmetrics, or prometheus
+    #[clap(short, long, default_value = "auto")]
+    format: om_nomnomnom::Format,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Prints the families of an exposition
+    Print {
+        #[clap(short, long)]
+        input: String,
+
+        /// auto, openmetrics, or prometheus
+        #[clap(short, long, default_value = "auto")]
+        format: om_nomnomnom::Format,
+    },
+
+    /// Validates expositions, reporting every problem found
+    Lint(lint::Args),
+
+    /// Converts an exposition to JSON, OpenMetrics or the Prometheus text format
+    Convert(convert::Args),
+
+    /// Compares two expositions: families, their metadata, series and values
+    Diff(diff::Args),
+
+    /// Prints the samples matching a PromQL-style selector
+    Query(query::Args),
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    match args.command {
+        Some(Command::Print { input, format }) => print(&input, format),
+        Some(Command::Lint(args)) => {
+            if lint::run(args)? > 0 {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Command::Convert(args)) => convert::run(args),
+        Some(Command::Diff(args)) => {
+            if diff::run(args)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Command::Query(args)) => {
+            if query::run(args)? == 0 {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        None => {
+            let input = args
+                .input
+                .ok_or(anyhow!("an --input or a subcommand is required"))?;
+            print(&input, args.format)
+        }
+    }
+}
+
+fn print(input: &str, format: om_nomnomnom::Format) -> Result<()> {
+    let om_data = std::fs::read_to_string(input)?;
+
+    let families = om_nomnomnom::parse_with_format(&om_data, format)?;
+
+    for (name, family) in families.iter() {
+        println!("{} ({:?})", name, family.metric_type);
+        if let Some(help) = family.help.borrow() {
+            println!("{}", help);
+        }
+
+        for sample in family.samples.iter() {
+            println!("  {:?}", sample);
+        }
+        println!();
+    }
+
+    Ok(())
+}
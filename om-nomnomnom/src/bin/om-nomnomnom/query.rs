@@ -0,0 +1,91 @@
+//! `om-nomnomnom query`: prints the samples picked by a selector, see
+//! [`om_nomnomnom::query::Selector`].
+
+use std::io::Read;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use om_nomnomnom::{query::Selector, Format};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// A PromQL-style selector, e.g. `http_requests_total{method=~"GET|POST",code!="500"}`
+    selector: String,
+
+    /// The expositions to search, `-` or none at all for stdin
+    files: Vec<String>,
+
+    /// How to print the samples
+    #[clap(long, value_enum, default_value = "text")]
+    format: Output,
+
+    /// auto, openmetrics, or prometheus
+    #[clap(long, default_value = "auto")]
+    input_format: Format,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum Output {
+    /// The sample lines, prefixed with their file if there are several
+    Text,
+    /// One sample per line, along with its file and the name and type of its family
+    Ndjson,
+}
+
+/// Prints the samples selected in each file on stdout, returning how many there were.
+pub fn run(args: Args) -> Result<usize> {
+    let selector: Selector = args.selector.parse()?;
+    let files = match args.files.is_empty() {
+        true => vec!["-".to_string()],
+        false => args.files,
+    };
+    let prefix = files.len() > 1;
+
+    let mut found = 0;
+    for file in files.iter() {
+        let data = read(file)?;
+        let set = om_nomnomnom::parse_with_format(&data, args.input_format)
+            .with_context(|| format!("couldn't parse {}", file))?;
+        let name = match file.as_str() {
+            "-" => "<stdin>",
+            file => file,
+        };
+
+        for (family, sample) in selector.select(&set) {
+            found += 1;
+            match args.format {
+                Output::Text => {
+                    let mut line = String::new();
+                    om_nomnomnom::serializer::write_sample(&mut line, sample)?;
+                    match prefix {
+                        true => print!("{}:{}", name, line),
+                        false => print!("{}", line),
+                    }
+                }
+                Output::Ndjson => {
+                    let mut line = serde_json::to_value(sample)?;
+                    line["file"] = Value::from(name);
+                    line["family"] = Value::from(family);
+                    line["type"] = serde_json::to_value(&set[family].metric_type)?;
+                    println!("{}", serde_json::to_string(&line)?);
+                }
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+fn read(file: &str) -> Result<String> {
+    match file {
+        "-" => {
+            let mut data = String::new();
+            std::io::stdin()
+                .read_to_string(&mut data)
+                .context("couldn't read stdin")?;
+            Ok(data)
+        }
+        path => std::fs::read_to_string(path).with_context(|| format!("couldn't read {}", path)),
+    }
+}
@@ -0,0 +1,381 @@
+mod convert {
+    use crate::convert::*;
+    use indoc::indoc;
+
+    #[test]
+    fn openmetrics_counters() {
+        let input = indoc! {r#"
+            # TYPE http_requests_total counter
+            http_requests_total{code="200"} 3
+            # TYPE up counter
+            up 1
+            # TYPE http_requests_created gauge
+            http_requests_created 1
+        "#};
+
+        let output = convert(input, Input::Prometheus, Output::Openmetrics).unwrap();
+        assert_eq!(
+            output,
+            indoc! {r#"
+                # TYPE http_requests counter
+                http_requests_total{code="200"} 3
+                # TYPE up counter
+                up_total 1
+                # TYPE http_requests_created gauge
+                http_requests_created 1
+                # EOF
+            "#}
+        );
+    }
+
+    #[test]
+    fn openmetrics_counters_clash() {
+        let input = indoc! {r#"
+            # TYPE foo_total counter
+            foo_total 3
+            # TYPE foo gauge
+            foo 1
+        "#};
+
+        let error = convert(input, Input::Prometheus, Output::Openmetrics).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "counter «foo_total» can't be renamed «foo», another family's name"
+        );
+    }
+
+    #[test]
+    fn prometheus_roundtrip() {
+        let input = indoc! {r#"
+            # TYPE a untyped
+            a{b="c"} 1 1500
+            # TYPE d_total counter
+            d_total 2 1700000000123
+        "#};
+
+        let openmetrics = convert(input, Input::Prometheus, Output::Openmetrics).unwrap();
+        assert_eq!(
+            openmetrics,
+            indoc! {r#"
+                # TYPE a unknown
+                a{b="c"} 1 1.5
+                # TYPE d counter
+                d_total 2 1700000000.123
+                # EOF
+            "#}
+        );
+
+        let prometheus = convert(&openmetrics, Input::Auto, Output::Prometheus).unwrap();
+        assert_eq!(prometheus, input);
+    }
+
+    #[test]
+    fn from_json() {
+        let input = indoc! {r#"
+            # TYPE b gauge
+            # HELP b Comes first
+            b 1
+            # TYPE a_seconds counter
+            # UNIT a_seconds seconds
+            a_seconds_total 2 123.5
+            # EOF
+        "#};
+
+        let json = convert(input, Input::Openmetrics, Output::Json).unwrap();
+        let output = convert(&json, Input::Json, Output::Openmetrics).unwrap();
+        assert_eq!(output, input);
+
+        let error = convert(r#"{"a": {}}"#, Input::Json, Output::Openmetrics).unwrap_err();
+        assert_eq!(error.to_string(), "couldn't read the families");
+    }
+
+    #[test]
+    fn non_finite_exemplars_roundtrip() {
+        let input = indoc! {r#"
+            # TYPE a counter
+            a_total{b="c"} 1 # {trace_id="abc"} NaN
+            a_total{b="d"} 2 # {trace_id="def"} +Inf 1.5
+            a_total{b="e"} 3 # {trace_id="ghi"} -Inf
+            # EOF
+        "#};
+
+        let json = convert(input, Input::Openmetrics, Output::Json).unwrap();
+        assert!(json.contains(r#""number": "NaN""#), "{}", json);
+        let output = convert(&json, Input::Json, Output::Openmetrics).unwrap();
+        assert_eq!(output, input);
+    }
+}
+
+mod diff {
+    use crate::diff::*;
+    use indoc::indoc;
+    use serde_json::json;
+
+    const OLD: &str = indoc! {r#"
+        # TYPE a histogram
+        a_bucket{code="200",le="1"} 1
+        a_bucket{code="200",le="+Inf"} 2
+        a_count{code="200"} 2
+        a_sum{code="200"} 3
+        # TYPE b gauge
+        b 1 10
+        b 2 20
+        # TYPE c gauge
+        c 1 10
+        # TYPE d counter
+        d_total 1
+        # EOF
+    "#};
+
+    const NEW: &str = indoc! {r#"
+        # TYPE a histogram
+        a_bucket{code="200",le="1"} 1
+        a_bucket{code="200",le="5"} 2
+        a_bucket{code="200",le="+Inf"} 3
+        a_count{code="200"} 3
+        a_sum{code="200"} 4
+        a_bucket{code="500",le="+Inf"} 1
+        a_count{code="500"} 1
+        a_sum{code="500"} 1
+        # TYPE b gauge
+        # HELP b Now with help
+        b 3 20
+        b 4 30
+        # TYPE c gauge
+        c 2 20
+        # TYPE e gauge
+        e 1
+        # EOF
+    "#};
+
+    #[test]
+    fn families() {
+        let (old, new) = (
+            om_nomnomnom::parse_metric_set(OLD).unwrap(),
+            om_nomnomnom::parse_metric_set(NEW).unwrap(),
+        );
+
+        assert_eq!(
+            text(&diff(&old, &new, false)),
+            indoc! {r#"
+                + e (gauge)
+                - d (counter)
+                ~ a
+                    + a_bucket{code="200",le="5"}
+                    + a{code="500"}
+                    a_bucket{code="200",le="+Inf"}: 2 -> 3 (+1)
+                    a_count{code="200"}: 2 -> 3 (+1)
+                    a_sum{code="200"}: 3 -> 4 (+1)
+                ~ b
+                    help: none -> "Now with help"
+                    + b @ 30
+                    - b @ 10
+                    b @ 20: 2 -> 3 (+1)
+                ~ c
+                    c: 1 -> 2 (+1)
+            "#}
+        );
+    }
+
+    #[test]
+    fn schema_only() {
+        let (old, new) = (
+            om_nomnomnom::parse_metric_set(OLD).unwrap(),
+            om_nomnomnom::parse_metric_set(NEW).unwrap(),
+        );
+
+        assert_eq!(
+            serde_json::to_value(diff(&old, &new, true)).unwrap(),
+            json!({
+                "added": [{"name": "e", "type": "gauge"}],
+                "removed": [{"name": "d", "type": "counter"}],
+                "changed": [
+                    {
+                        "name": "a",
+                        "added": [r#"a_bucket{code="200",le="5"}"#, r#"a{code="500"}"#],
+                        "removed": [],
+                        "values": [],
+                    },
+                    {
+                        "name": "b",
+                        "help": {"old": null, "new": "Now with help"},
+                        "added": ["b @ 30"],
+                        "removed": ["b @ 10"],
+                        "values": [],
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn family() {
+        let old = om_nomnomnom::parse_metric_set(OLD).unwrap();
+        let new = om_nomnomnom::parse_metric_set(NEW).unwrap();
+
+        assert!(diff_family("a", &old["a"], &old["a"], false).is_none());
+        assert!(diff_family("c", &old["c"], &new["c"], true).is_none());
+
+        let a = diff_family("a", &old["a"], &new["a"], false).unwrap();
+        assert_eq!(
+            serde_json::to_value(a).unwrap()["values"][0],
+            json!({
+                "series": r#"a_bucket{code="200",le="+Inf"}"#,
+                "old": 2,
+                "new": 3,
+                "delta": 1,
+            })
+        );
+    }
+
+    #[test]
+    fn exact_delta() {
+        let (old, new) = (
+            om_nomnomnom::parse_metric_set("# TYPE a gauge\na 18446744073709551614\n# EOF\n")
+                .unwrap(),
+            om_nomnomnom::parse_metric_set("# TYPE a gauge\na 18446744073709551615\n# EOF\n")
+                .unwrap(),
+        );
+
+        // As floats the two are the same, and the delta would be 0
+        assert_eq!(
+            text(&diff(&old, &new, false)),
+            "~ a\n    a: 18446744073709551614 -> 18446744073709551615 (+1)\n"
+        );
+    }
+}
+
+mod lint {
+    use crate::lint::*;
+    use indoc::indoc;
+    use om_nomnomnom::parser::ParserOptions;
+    use serde_json::json;
+
+    /// A syntax error, a family that doesn't validate and a missing EOF
+    const DATA: &str = indoc! {r#"
+        # TYPE a gauge
+        a{ 1
+        # TYPE b counter
+        b_total -1
+        # TYPE c gauge
+        c{ 1
+        c 1
+    "#};
+
+    fn problems() -> Vec<Problem> {
+        check(DATA.as_bytes(), &ParserOptions::new())
+            .into_iter()
+            .map(|error| Problem {
+                file: "metrics.txt".to_string(),
+                error,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn text_output() {
+        let lines: Vec<_> = problems().iter().map(text).collect();
+        assert_eq!(
+            vec![
+                "metrics.txt:2:2: syntax: unexpected input (Tag)",
+                "metrics.txt:4:9: bad-counter: malformed «Counter» MetricFamily in «b»",
+                "metrics.txt:6:2: syntax: unexpected input (Tag)",
+                "metrics.txt:7:4: missing-eof: the exposition MUST end with «# EOF»",
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let problems = problems();
+        assert_eq!(
+            json!({
+                "file": "metrics.txt",
+                "line": 4,
+                "column": 9,
+                "rule": "bad-counter",
+                "message": "malformed «Counter» MetricFamily",
+                "family": "b",
+                "text": "b_total -1",
+            }),
+            json(&problems[1])
+        );
+        assert_eq!(
+            json!({
+                "file": "metrics.txt",
+                "line": 7,
+                "column": 4,
+                "rule": "missing-eof",
+                "message": "the exposition MUST end with «# EOF»",
+                "family": null,
+                "text": "c 1",
+            }),
+            json(&problems[3])
+        );
+    }
+
+    #[test]
+    fn sarif_output() {
+        let log = sarif(&problems());
+        let run = &log["runs"][0];
+
+        // Each rule is described once, however many times it's broken
+        assert_eq!(
+            json!([
+                {
+                    "id": "syntax",
+                    "shortDescription": {
+                        "text": "the line doesn't follow the exposition format"
+                    },
+                },
+                {
+                    "id": "bad-counter",
+                    "shortDescription": { "text": "malformed «Counter» MetricFamily" },
+                },
+                {
+                    "id": "missing-eof",
+                    "shortDescription": { "text": "the exposition MUST end with «# EOF»" },
+                },
+            ]),
+            run["tool"]["driver"]["rules"]
+        );
+
+        assert_eq!(4, run["results"].as_array().unwrap().len());
+        assert_eq!(
+            json!({
+                "ruleId": "syntax",
+                "level": "error",
+                "message": { "text": "unexpected input (Tag)" },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": "metrics.txt" },
+                        "region": {
+                            "startLine": 6,
+                            "startColumn": 2,
+                            "snippet": { "text": "c{ 1" },
+                        },
+                    },
+                }],
+            }),
+            run["results"][2]
+        );
+
+        // The parser reports the start of the offending value
+        assert_eq!(
+            json!({
+                "startLine": 4,
+                "startColumn": 9,
+                "snippet": { "text": "b_total -1" },
+            }),
+            run["results"][1]["locations"][0]["physicalLocation"]["region"]
+        );
+    }
+
+    #[test]
+    fn not_utf8() {
+        let errors = check(b"a 1\n\xff 1\n", &ParserOptions::new());
+        let rules: Vec<_> = errors.iter().map(|error| error.rule_id()).collect();
+        assert_eq!(vec!["syntax"], rules);
+    }
+}
@@ -7,21 +7,34 @@ use itertools::{Itertools, Position};
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_while},
-    character::complete::{alphanumeric1, satisfy},
-    combinator::{eof, map, map_res, opt, peek, value},
+    character::complete::{digit1, one_of, satisfy},
+    combinator::{eof, map, map_opt, map_res, not, opt, peek, recognize, rest, value},
     multi::separated_list0,
     sequence::{delimited, preceded, terminated, tuple},
     IResult,
 };
 use serde::Serializer;
 use serde_derive::Serialize;
+use smallvec::SmallVec;
 
-mod types;
+use crate::{Location, OmError};
+
+pub(crate) mod types;
 use types::*;
 
+/// Tokenizes the Prometheus text exposition format (version 0.0.4)
+pub(crate) mod prometheus;
+
+/// Tokenizes an OpenMetrics exposition held in bytes, a sample with up to four labels without
+/// allocating
+pub mod bytes;
+
+/// The labels of a sample or exemplar, the first few are kept inline
+pub type Labels<'a> = SmallVec<[Label<'a>; 4]>;
+
 #[derive(Clone, Debug, Serialize)]
 pub struct Exemplar<'a> {
-    pub labels: Vec<Label<'a>>,
+    pub labels: Labels<'a>,
     pub number: f64,
     pub timestamp: Option<f64>,
 }
@@ -35,7 +48,7 @@ pub struct Label<'a> {
 #[derive(Clone, Debug, Serialize)]
 pub struct Sample<'a> {
     pub name: &'a str,
-    pub labels: Option<Vec<Label<'a>>>,
+    pub labels: Option<Labels<'a>>,
     pub number: MetricNumber,
     pub timestamp: Option<f64>,
     pub exemplar: Option<Exemplar<'a>>,
@@ -57,6 +70,15 @@ pub enum MetricDescriptor<'a> {
     },
 }
 
+/// A single line of an exposition document and the token it was lexed into
+#[derive(Clone, Debug, Serialize)]
+pub struct Line<'a> {
+    /// 1-based line number
+    pub number: usize,
+    pub text: &'a str,
+    pub token: MetricToken<'a>,
+}
+
 #[derive(Debug, Serialize)]
 struct MetricFamily<'a> {
     tokens: Vec<MetricToken<'a>>,
@@ -66,8 +88,12 @@ struct MetricFamily<'a> {
 pub enum MetricNumber {
     Float(f64),
     Integer(i64),
+    /// An integer too large to fit in an `i64`
+    Unsigned(u64),
 }
 
+// Samples keep their labels inline, boxing them would mean an allocation per line
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, Serialize)]
 pub enum MetricToken<'a> {
     Descriptor(MetricDescriptor<'a>),
@@ -92,11 +118,14 @@ impl<'a> Exemplar<'a> {
     /// ```abnf
     /// exemplar = SP HASH SP labels SP number [SP timestamp]
     /// ```
-    fn nom(input: &'a str) -> IResult<&str, Self> {
+    fn nom(input: &'a str) -> IResult<&'a str, Self> {
         map(
             tuple((
                 tag(" # "),
-                delimited(tag("{"), separated_list0(tag(","), Label::nom), tag("}")),
+                map(
+                    delimited(tag("{"), separated_list0(tag(","), Label::nom), tag("}")),
+                    Labels::from_vec,
+                ),
                 tag(" "),
                 floatlike,
                 opt(preceded(single_space, realnumber)),
@@ -112,7 +141,7 @@ impl<'a> Exemplar<'a> {
 
 impl<'a> Label<'a> {
     #[tracing::instrument]
-    fn nom(input: &'a str) -> IResult<&str, Self> {
+    fn nom(input: &'a str) -> IResult<&'a str, Self> {
         map(
             tuple((
                 Self::label_name1,
@@ -133,7 +162,7 @@ impl<'a> Label<'a> {
     /// label-name-initial-char = ALPHA / "_"
     /// ```
     #[tracing::instrument]
-    fn label_name1(input: &str) -> IResult<&str, &str> {
+    pub(crate) fn label_name1(input: &str) -> IResult<&str, &str> {
         peek(satisfy(|c| c.is_alphabetic() || c == '_'))(input)?;
 
         take_while(|item: char| item.is_alphanumeric() || item == '_')(input)
@@ -141,7 +170,7 @@ impl<'a> Label<'a> {
 }
 
 impl<'a> Sample<'a> {
-    fn nom(input: &'a str) -> IResult<&str, Self> {
+    fn nom(input: &'a str) -> IResult<&'a str, Self> {
         let (input, name) = metric_name1(input)?;
         let (input, labels) = map(
             opt(delimited(
@@ -150,7 +179,7 @@ impl<'a> Sample<'a> {
                 tag("}"),
             )),
             |labels| match labels {
-                Some(labels) if labels.len() > 0 => Some(labels),
+                Some(labels) if !labels.is_empty() => Some(Labels::from_vec(labels)),
                 Some(_) => None,
                 None => None,
             },
@@ -179,7 +208,7 @@ impl<'a> MetricDescriptor<'a> {
     /// type = %d84.89.80.69
     /// metric-descriptor = HASH SP type SP metricname SP metric-type LF
     /// ```
-    fn nom_type_descriptor(input: &'a str) -> IResult<&str, Self> {
+    fn nom_type_descriptor(input: &'a str) -> IResult<&'a str, Self> {
         map(
             terminated(
                 tuple((
@@ -201,7 +230,7 @@ impl<'a> MetricDescriptor<'a> {
     /// help = %d72.69.76.80
     /// metric-descriptor =/ HASH SP help SP metricname SP escaped-string LF
     /// ```
-    fn nom_help_descriptor(input: &'a str) -> IResult<&str, Self> {
+    fn nom_help_descriptor(input: &'a str) -> IResult<&'a str, Self> {
         map(
             tuple((
                 tag("HELP"),
@@ -212,7 +241,7 @@ impl<'a> MetricDescriptor<'a> {
                     opt(escaped_string1),
                     // Yiiiiikes
                     |s| match s {
-                        Some(s) if s.is_empty() => None,
+                        Some("") => None,
                         Some(s) => Some(s),
                         None => None,
                     },
@@ -220,7 +249,7 @@ impl<'a> MetricDescriptor<'a> {
             )),
             |(_, _, metric_name, _, help_text)| MetricDescriptor::Help {
                 metric_name,
-                help_text: help_text,
+                help_text,
             },
         )(input)
     }
@@ -229,27 +258,35 @@ impl<'a> MetricDescriptor<'a> {
     /// unit = %d85.78.73.84
     /// metric-descriptor =/ HASH SP unit SP metricname SP *metricname-char LF
     /// ```
-    fn nom_unit_descriptor(input: &'a str) -> IResult<&str, Self> {
+    ///
+    /// The unit is the rest of the line, or `None` if the separating space is missing.  The parser
+    /// checks it against the metric name and charset.
+    fn nom_unit_descriptor(input: &'a str) -> IResult<&'a str, Self> {
         map(
             tuple((
                 tag("UNIT"),
                 single_space,
                 metric_name1,
-                opt(tuple((single_space, alphanumeric1))),
+                opt(preceded(single_space, rest)),
             )),
-            |(_, _, metric_name, unit)| MetricDescriptor::Unit {
-                metric_name,
-                unit: unit.map(|t| t.1),
-            },
+            |(_, _, metric_name, unit)| MetricDescriptor::Unit { metric_name, unit },
         )(input)
     }
 
+    pub(crate) fn metric_name(&self) -> &'a str {
+        match self {
+            Self::Type { metric_name, .. }
+            | Self::Help { metric_name, .. }
+            | Self::Unit { metric_name, .. } => metric_name,
+        }
+    }
+
     /// ```abnf
     /// metric-descriptor = HASH SP type SP metricname SP metric-type LF
     /// metric-descriptor =/ HASH SP help SP metricname SP escaped-string LF
     /// metric-descriptor =/ HASH SP unit SP metricname SP *metricname-char LF
     /// ```
-    fn nom(input: &'a str) -> IResult<&str, Self> {
+    fn nom(input: &'a str) -> IResult<&'a str, Self> {
         let (input, _) = tuple((tag("#"), single_space))(input)?;
         alt((
             Self::nom_type_descriptor,
@@ -260,13 +297,13 @@ impl<'a> MetricDescriptor<'a> {
 }
 
 impl<'a> MetricFamily<'a> {
-    fn nom_last_line(input: &'a str) -> IResult<&str, MetricToken<'a>> {
+    fn nom_last_line(input: &'a str) -> IResult<&'a str, MetricToken<'a>> {
         alt((
             value(MetricToken::Eof, terminated(tag("# EOF"), eof)),
             value(MetricToken::Empty, eof),
         ))(input)
     }
-    fn nom(input: &'a str) -> IResult<&str, MetricToken<'a>> {
+    fn nom(input: &'a str) -> IResult<&'a str, MetricToken<'a>> {
         alt((
             map(MetricDescriptor::nom, MetricToken::Descriptor),
             map(Sample::nom, MetricToken::Metric),
@@ -276,12 +313,27 @@ impl<'a> MetricFamily<'a> {
 }
 
 impl<'a> MetricNumber {
-    fn nom(input: &'a str) -> IResult<&str, Self> {
+    /// Integers are kept as such as long as they fit into an `i64` or `u64`, anything else is
+    /// treated as a float.
+    fn nom(input: &'a str) -> IResult<&'a str, Self> {
         alt((
+            map_opt(
+                terminated(
+                    recognize(tuple((opt(one_of("+-")), digit1))),
+                    not(one_of(".eE")),
+                ),
+                Self::from_integer,
+            ),
             map(floatlike, Self::Float),
-            map(nom::character::complete::i64, Self::Integer),
         ))(input)
     }
+
+    fn from_integer(input: &str) -> Option<Self> {
+        match input.parse::<i64>() {
+            Ok(n) => Some(Self::Integer(n)),
+            Err(_) => input.parse::<u64>().ok().map(Self::Unsigned),
+        }
+    }
 }
 
 impl serde::Serialize for MetricNumber {
@@ -302,6 +354,7 @@ impl serde::Serialize for MetricNumber {
                 }
             }
             Self::Integer(n) => s.serialize_i64(*n),
+            Self::Unsigned(n) => s.serialize_u64(*n),
         }
     }
 }
@@ -344,19 +397,112 @@ impl FromStr for MetricType {
 }
 
 #[tracing::instrument(skip(input))]
-pub(super) fn exposition<'a>(input: &'a str) -> IResult<&str, Vec<MetricToken<'a>>> {
+pub(super) fn exposition<'a>(input: &'a str) -> Result<Vec<Line<'a>>, OmError> {
     debug!(input);
-    let data: Result<Vec<_>, _> = input
-        .split("\n")
+    lines(input).collect()
+}
+
+/// Lexes every line it can, returning the errors for the lines it couldn't instead of stopping at
+/// the first one.
+#[tracing::instrument(skip(input))]
+pub(crate) fn exposition_lenient<'a>(input: &'a str) -> (Vec<Line<'a>>, Vec<OmError>) {
+    debug!(input);
+    partition(lines(input))
+}
+
+fn lines(input: &str) -> impl Iterator<Item = Result<Line<'_>, OmError>> {
+    input
+        .split('\n')
         .with_position()
-        .map(|line| {
+        .enumerate()
+        .map(|(index, line)| {
             debug!(?line);
             match line {
-                Position::First(line) | Position::Middle(line) => MetricFamily::nom(line),
-                Position::Only(line) | Position::Last(line) => MetricFamily::nom_last_line(line),
+                Position::First(line) | Position::Middle(line) => lex_line(index + 1, line, false),
+                Position::Only(line) | Position::Last(line) => lex_line(index + 1, line, true),
             }
         })
-        .collect();
-    let data = data?.into_iter().map(|x| x.1).collect();
-    Ok(("", data))
+}
+
+/// Splits lexed lines from their errors
+pub(crate) fn partition<'a, I>(lines: I) -> (Vec<Line<'a>>, Vec<OmError>)
+where
+    I: Iterator<Item = Result<Line<'a>, OmError>>,
+{
+    let mut ok = vec![];
+    let mut errors = vec![];
+    for line in lines {
+        match line {
+            Ok(line) => ok.push(line),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    (ok, errors)
+}
+
+/// Lexes a single line.  Only the `last` line of an exposition may be empty.
+pub(crate) fn lex_line(number: usize, text: &str, last: bool) -> Result<Line<'_>, OmError> {
+    let result = match last {
+        false => MetricFamily::nom(text),
+        true => MetricFamily::nom_last_line(text),
+    };
+
+    match result {
+        Ok((_, token)) => Ok(Line {
+            number,
+            text,
+            token,
+        }),
+        // `alt` only reports the error from its last branch, so retry each kind of line and
+        // report whichever got the furthest.
+        Err(err) => Err(lex_error(
+            number,
+            text,
+            [
+                MetricDescriptor::nom(text).err(),
+                Sample::nom(text).err(),
+                Some(err),
+            ],
+        )),
+    }
+}
+
+/// Cheaply finds the metric name a line refers to, and whether the line is a descriptor, without
+/// lexing the rest of it.
+pub(crate) fn line_name(text: &str) -> Option<(&str, bool)> {
+    match text.starts_with('#') {
+        true => MetricDescriptor::nom(text)
+            .ok()
+            .map(|(_, descriptor)| (descriptor.metric_name(), true)),
+        false => metric_name1(text).ok().map(|(_, name)| (name, false)),
+    }
+}
+
+/// Builds a [`OmError::LexError`] out of whichever of `errors` got the furthest into the line.
+fn lex_error<'a, I>(number: usize, text: &'a str, errors: I) -> OmError
+where
+    I: IntoIterator<Item = Option<nom::Err<nom::error::Error<&'a str>>>>,
+{
+    let furthest = errors
+        .into_iter()
+        .flatten()
+        .filter_map(|err| match err {
+            nom::Err::Error(err) | nom::Err::Failure(err) => Some(err),
+            nom::Err::Incomplete(_) => None,
+        })
+        .min_by_key(|err| err.input.len());
+
+    let (offset, message) = match furthest {
+        Some(err) => (
+            text.len() - err.input.len(),
+            format!("unexpected input ({})", err.code.description()),
+        ),
+        None => (0, "unexpected input".to_string()),
+    };
+
+    OmError::LexError {
+        message,
+        location: Location::new(number, text[..offset].chars().count() + 1, text, None),
+    }
 }
@@ -0,0 +1,297 @@
+#[allow(unused)]
+use tracing::{debug, error, info, span, trace, warn, Instrument, Level};
+
+use memchr::{memchr, memchr2};
+use nom::IResult;
+
+use super::{
+    types::*, Exemplar, Label, Labels, Line, MetricDescriptor, MetricNumber, MetricToken, Sample,
+};
+use crate::{Location, OmError};
+
+/// Lexes a whole exposition held in bytes, see [`Lines`].
+pub fn exposition(input: &[u8]) -> Result<Vec<Line<'_>>, OmError> {
+    lines(input).collect()
+}
+
+/// Lexes the lines of an exposition held in bytes one at a time, see [`Lines`].
+pub fn lines(input: &[u8]) -> Lines<'_> {
+    lines_at(input, 1, true)
+}
+
+/// Lexes a run of lines starting at line `number`.  Unless the run goes on `to_end` of the
+/// exposition it must end with a newline, and none of its lines is the last one.
+pub(crate) fn lines_at(input: &[u8], number: usize, to_end: bool) -> Lines<'_> {
+    Lines {
+        rest: Some(input),
+        number: number - 1,
+        to_end,
+    }
+}
+
+/// An iterator over the lexed lines of an exposition held in bytes.
+///
+/// Lines are found with `memchr` and lexed by hand: a token borrows everything from the input and
+/// keeps up to four labels inline, so lexing a sample doesn't allocate.  The tokens are the same as
+/// the nom lexer's behind [`parse`](crate::parse).  A line the fast path can't lex is handed over
+/// to that lexer, which reports the error or lexes the rare line the fast path leaves alone, such
+/// as one with a non-ASCII name.
+#[derive(Clone, Debug)]
+pub struct Lines<'a> {
+    /// Whatever follows the last newline found, `None` once the last line has been lexed
+    rest: Option<&'a [u8]>,
+    /// The number of the last line lexed
+    number: usize,
+    /// Whether the input goes on to the end of the exposition
+    to_end: bool,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = Result<Line<'a>, OmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest?;
+        if rest.is_empty() && !self.to_end {
+            self.rest = None;
+            return None;
+        }
+        self.number += 1;
+
+        // Only the last line may be empty, so it's the one after the last newline
+        let (bytes, last) = match memchr(b'\n', rest) {
+            Some(end) => {
+                self.rest = Some(&rest[end + 1..]);
+                (&rest[..end], false)
+            }
+            None => {
+                self.rest = None;
+                (rest, true)
+            }
+        };
+
+        let number = self.number;
+        Some(decode(number, bytes).and_then(|text| lex_line(number, text, last)))
+    }
+}
+
+/// Lexes a single line, see [`Lines`].
+pub(crate) fn lex_line(number: usize, text: &str, last: bool) -> Result<Line<'_>, OmError> {
+    match token(text, last) {
+        Some(token) => Ok(Line {
+            number,
+            text,
+            token,
+        }),
+        None => super::lex_line(number, text, last),
+    }
+}
+
+/// Checks that a line is valid UTF-8
+pub(crate) fn decode(line: usize, bytes: &[u8]) -> Result<&str, OmError> {
+    std::str::from_utf8(bytes).map_err(|err| {
+        let text = String::from_utf8_lossy(bytes);
+        let valid = String::from_utf8_lossy(&bytes[..err.valid_up_to()]);
+        OmError::LexError {
+            message: "invalid UTF-8".to_string(),
+            location: Location::new(line, valid.chars().count() + 1, &text, None),
+        }
+    })
+}
+
+/// The fast path of [`lex_line`], `None` for any line it doesn't handle
+fn token(text: &str, last: bool) -> Option<MetricToken<'_>> {
+    match (text.as_bytes().first(), last) {
+        _ if text == "# EOF" => Some(MetricToken::Eof),
+        (None, true) => Some(MetricToken::Empty),
+        (Some(b'#'), false) => descriptor(text).map(MetricToken::Descriptor),
+        (Some(_), false) => sample(text).map(MetricToken::Metric),
+        _ => None,
+    }
+}
+
+/// ```abnf
+/// metric-descriptor = HASH SP type SP metricname SP metric-type LF
+/// metric-descriptor =/ HASH SP help SP metricname SP escaped-string LF
+/// metric-descriptor =/ HASH SP unit SP metricname SP *metricname-char LF
+/// ```
+///
+/// As with the nom lexer, the help text stops at an unescaped quote and anything after the name
+/// of a unit descriptor is ignored when the separating space is missing.
+fn descriptor(text: &str) -> Option<MetricDescriptor<'_>> {
+    let text = text.strip_prefix("# ")?;
+
+    if let Some(rest) = text.strip_prefix("TYPE ") {
+        let (metric_name, rest) = name(rest, true)?;
+        let metric_type = rest.strip_prefix(' ')?.parse().ok()?;
+        Some(MetricDescriptor::Type {
+            metric_name,
+            metric_type,
+        })
+    } else if let Some(rest) = text.strip_prefix("HELP ") {
+        let (metric_name, rest) = name(rest, true)?;
+        let rest = rest.strip_prefix(' ')?;
+        let help_text = escaped(rest)
+            .map(|end| &rest[..end])
+            .filter(|help_text| !help_text.is_empty());
+        Some(MetricDescriptor::Help {
+            metric_name,
+            help_text,
+        })
+    } else if let Some(rest) = text.strip_prefix("UNIT ") {
+        let (metric_name, rest) = name(rest, true)?;
+        let unit = rest.strip_prefix(' ');
+        Some(MetricDescriptor::Unit { metric_name, unit })
+    } else {
+        None
+    }
+}
+
+/// ```abnf
+/// sample = metricname [labels] SP number [SP timestamp] [exemplar] LF
+/// ```
+fn sample(text: &str) -> Option<Sample<'_>> {
+    let (name, mut rest) = name(text, true)?;
+
+    let mut labels = None;
+    if let Some(after) = rest.strip_prefix('{') {
+        let (list, after) = label_list(after)?;
+        labels = Some(list).filter(|list| !list.is_empty());
+        rest = after;
+    }
+
+    let (number, mut rest) = word(rest.strip_prefix(' ')?);
+    let number = complete(MetricNumber::nom, number)?;
+
+    let mut timestamp = None;
+    if !rest.starts_with(" # ") {
+        if let Some(after) = rest.strip_prefix(' ') {
+            let (word, after) = word(after);
+            timestamp = Some(complete(realnumber, word)?);
+            rest = after;
+        }
+    }
+
+    let exemplar = match rest.strip_prefix(" # ") {
+        Some(rest) => Some(exemplar(rest)?),
+        None if rest.is_empty() => None,
+        None => return None,
+    };
+
+    Some(Sample {
+        name,
+        labels,
+        number,
+        timestamp,
+        exemplar,
+    })
+}
+
+/// ```abnf
+/// exemplar = SP HASH SP labels SP number [SP timestamp]
+/// ```
+///
+/// Whatever follows the leading `" # "`, up to the end of the line.
+fn exemplar(text: &str) -> Option<Exemplar<'_>> {
+    let (labels, rest) = label_list(text.strip_prefix('{')?)?;
+
+    let (number, rest) = word(rest.strip_prefix(' ')?);
+    let number = complete(floatlike, number)?;
+
+    let timestamp = match rest.strip_prefix(' ') {
+        Some(rest) => Some(complete(realnumber, rest)?),
+        None if rest.is_empty() => None,
+        None => return None,
+    };
+
+    Some(Exemplar {
+        labels,
+        number,
+        timestamp,
+    })
+}
+
+/// Lexes the labels following an opening brace, up to and including the closing one
+fn label_list(text: &str) -> Option<(Labels<'_>, &str)> {
+    let mut labels = Labels::new();
+    if let Some(rest) = text.strip_prefix('}') {
+        return Some((labels, rest));
+    }
+
+    let mut rest = text;
+    loop {
+        let (name, after) = name(rest, false)?;
+        let after = after.strip_prefix("=\"")?;
+        let end = escaped(after)?;
+        let value = Some(&after[..end]).filter(|value| !value.is_empty());
+        labels.push(Label { name, value });
+
+        let after = after[end..].strip_prefix('"')?;
+        match after.strip_prefix(',') {
+            Some(after) => rest = after,
+            None => return Some((labels, after.strip_prefix('}')?)),
+        }
+    }
+}
+
+/// Splits off a metric name, or a label name if `colon` is false.  Names with non-ASCII
+/// characters are left to the nom lexer, which accepts any alphanumeric character.
+fn name(text: &str, colon: bool) -> Option<(&str, &str)> {
+    let is_initial =
+        |byte: u8| byte.is_ascii_alphabetic() || byte == b'_' || (colon && byte == b':');
+    let bytes = text.as_bytes();
+
+    if !is_initial(*bytes.first()?) {
+        return None;
+    }
+
+    let end = bytes
+        .iter()
+        .position(|byte| !(is_initial(*byte) || byte.is_ascii_digit()))
+        .unwrap_or(bytes.len());
+    match bytes.get(end) {
+        Some(byte) if !byte.is_ascii() => None,
+        _ => Some(text.split_at(end)),
+    }
+}
+
+/// ```abnf
+/// escaped-string = *escaped-char
+/// ```
+///
+/// Finds where an escaped string ends, which is at the first unescaped double quote or at the end
+/// of `text`.  A backslash escapes whichever character follows it, so a trailing one is an error.
+fn escaped(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut at = 0;
+
+    while let Some(found) = memchr2(b'"', b'\\', &bytes[at..]) {
+        let found = at + found;
+        if bytes[found] == b'"' {
+            return Some(found);
+        }
+
+        let escaped = text[found + 1..].chars().next()?;
+        at = found + 1 + escaped.len_utf8();
+    }
+
+    Some(bytes.len())
+}
+
+/// Splits `text` at the next space
+fn word(text: &str) -> (&str, &str) {
+    match memchr(b' ', text.as_bytes()) {
+        Some(end) => text.split_at(end),
+        None => (text, ""),
+    }
+}
+
+/// Runs one of the nom lexer's number parsers over the whole of `text`
+fn complete<'a, O, F>(mut parser: F, text: &'a str) -> Option<O>
+where
+    F: FnMut(&'a str) -> IResult<&'a str, O>,
+{
+    match parser(text) {
+        Ok(("", output)) => Some(output),
+        _ => None,
+    }
+}
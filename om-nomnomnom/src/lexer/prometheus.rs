@@ -0,0 +1,200 @@
+#[allow(unused)]
+use tracing::{debug, error, info, span, trace, warn, Instrument, Level};
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while, take_while1},
+    character::complete::i64,
+    combinator::{eof, map, not, opt, peek, rest, value},
+    multi::separated_list0,
+    sequence::{delimited, preceded, terminated, tuple},
+    IResult,
+};
+
+use super::{
+    lex_error, partition, types::*, Label, Labels, Line, MetricDescriptor, MetricNumber,
+    MetricToken, MetricType, Sample,
+};
+use crate::OmError;
+
+/// Tokens can be separated by any number of blanks and/or tabs
+fn blank(input: &str) -> IResult<&str, &str> {
+    take_while(|c| c == ' ' || c == '\t')(input)
+}
+
+fn blank1(input: &str) -> IResult<&str, &str> {
+    take_while1(|c| c == ' ' || c == '\t')(input)
+}
+
+/// ```text
+/// # TYPE metric_name counter|gauge|histogram|summary|untyped
+/// ```
+fn type_descriptor(input: &str) -> IResult<&str, MetricDescriptor<'_>> {
+    map(
+        tuple((
+            tag("#"),
+            blank1,
+            tag("TYPE"),
+            blank1,
+            metric_name1,
+            blank1,
+            metric_type,
+            blank,
+            eof,
+        )),
+        |(_, _, _, _, metric_name, _, metric_type, _, _)| MetricDescriptor::Type {
+            metric_name,
+            metric_type,
+        },
+    )(input)
+}
+
+/// ```text
+/// # HELP metric_name docstring
+/// ```
+fn help_descriptor(input: &str) -> IResult<&str, MetricDescriptor<'_>> {
+    map(
+        tuple((
+            tag("#"),
+            blank1,
+            tag("HELP"),
+            blank1,
+            metric_name1,
+            opt(preceded(blank1, rest)),
+        )),
+        |(_, _, _, _, metric_name, help_text)| MetricDescriptor::Help {
+            metric_name,
+            help_text: help_text.filter(|text| !text.is_empty()),
+        },
+    )(input)
+}
+
+fn descriptor(input: &str) -> IResult<&str, MetricDescriptor<'_>> {
+    alt((type_descriptor, help_descriptor))(input)
+}
+
+/// Any other line starting with a `#` is a comment
+fn comment(input: &str) -> IResult<&str, &str> {
+    preceded(
+        tuple((
+            tag("#"),
+            not(peek(tuple((
+                blank1,
+                alt((tag("HELP"), tag("TYPE"))),
+                alt((blank1, eof)),
+            )))),
+        )),
+        rest,
+    )(input)
+}
+
+fn metric_type(input: &str) -> IResult<&str, MetricType> {
+    alt((
+        value(MetricType::Counter, tag("counter")),
+        value(MetricType::Gauge, tag("gauge")),
+        value(MetricType::Histogram, tag("histogram")),
+        value(MetricType::Summary, tag("summary")),
+        value(MetricType::Unknown, tag("untyped")),
+    ))(input)
+}
+
+fn label(input: &str) -> IResult<&str, Label<'_>> {
+    map(
+        tuple((
+            Label::label_name1,
+            blank,
+            tag("="),
+            blank,
+            delimited(tag(r#"""#), opt(escaped_string1), tag(r#"""#)),
+        )),
+        |(name, _, _, _, value)| Label { name, value },
+    )(input)
+}
+
+/// A trailing comma is permitted after the last label
+fn labels(input: &str) -> IResult<&str, Vec<Label<'_>>> {
+    delimited(
+        tuple((tag("{"), blank)),
+        terminated(
+            separated_list0(tuple((blank, tag(","), blank)), label),
+            opt(tuple((blank, tag(",")))),
+        ),
+        tuple((blank, tag("}"))),
+    )(input)
+}
+
+/// ```text
+/// metric_name [ "{" label_name "=" `"` label_value `"` { "," ... } [ "," ] "}" ] value [ timestamp ]
+/// ```
+///
+/// The timestamp is an integer number of milliseconds since the epoch, it's converted to seconds
+/// to match the OpenMetrics model.
+fn sample(input: &str) -> IResult<&str, Sample<'_>> {
+    let (input, name) = metric_name1(input)?;
+    let (input, labels) = opt(preceded(blank, labels))(input)?;
+    let (input, number) = match labels {
+        Some(_) => preceded(blank, MetricNumber::nom)(input)?,
+        None => preceded(blank1, MetricNumber::nom)(input)?,
+    };
+    let (input, timestamp) = opt(preceded(blank1, i64))(input)?;
+    let (input, _) = tuple((blank, eof))(input)?;
+
+    Ok((
+        input,
+        Sample {
+            name,
+            labels: labels
+                .filter(|labels| !labels.is_empty())
+                .map(Labels::from_vec),
+            number,
+            timestamp: timestamp.map(|ms| ms as f64 / 1000.),
+            exemplar: None,
+        },
+    ))
+}
+
+fn line(input: &str) -> IResult<&str, MetricToken<'_>> {
+    preceded(
+        blank,
+        alt((
+            map(descriptor, MetricToken::Descriptor),
+            value(MetricToken::Empty, comment),
+            map(sample, MetricToken::Metric),
+            value(MetricToken::Empty, eof),
+        )),
+    )(input)
+}
+
+#[tracing::instrument(skip(input))]
+pub(crate) fn exposition<'a>(input: &'a str) -> Result<Vec<Line<'a>>, OmError> {
+    debug!(input);
+    lines(input).collect()
+}
+
+/// Lexes every line it can, see [`super::exposition_lenient`].
+#[tracing::instrument(skip(input))]
+pub(crate) fn exposition_lenient<'a>(input: &'a str) -> (Vec<Line<'a>>, Vec<OmError>) {
+    debug!(input);
+    partition(lines(input))
+}
+
+fn lines(input: &str) -> impl Iterator<Item = Result<Line<'_>, OmError>> {
+    input
+        .split('\n')
+        .enumerate()
+        .map(|(index, text)| match line(text) {
+            Ok((_, token)) => Ok(Line {
+                number: index + 1,
+                text,
+                token,
+            }),
+            Err(err) => {
+                let trimmed = blank(text).map(|(trimmed, _)| trimmed).unwrap_or(text);
+                Err(lex_error(
+                    index + 1,
+                    text,
+                    [descriptor(trimmed).err(), sample(trimmed).err(), Some(err)],
+                ))
+            }
+        })
+}
@@ -48,7 +48,7 @@ pub(super) fn single_space(input: &str) -> IResult<&str, &str> {
 /// escaped-char =/ BS ("n" / DQUOTE / BS)
 /// escaped-char =/ BS normal-char
 /// ```
-pub(super) fn escaped_string1(input: &str) -> IResult<&str, &str> {
+pub(crate) fn escaped_string1(input: &str) -> IResult<&str, &str> {
     escaped(normal_char1, '\\', alt((one_of(r#""\"#), normal_char)))(input)
 }
 
@@ -59,13 +59,13 @@ pub(super) fn escaped_string1(input: &str) -> IResult<&str, &str> {
 /// metricname-initial-char = ALPHA / "_" / ":"
 /// ```
 #[tracing::instrument]
-pub(super) fn metric_name1(input: &str) -> IResult<&str, &str> {
+pub(crate) fn metric_name1(input: &str) -> IResult<&str, &str> {
     peek(satisfy(|c| c.is_alphabetic() || c == '_' || c == ':'))(input)?;
 
     take_while(|item: char| item.is_alphanumeric() || item == '_' || item == ':')(input)
 }
 
-pub(super) fn realnumber<'a>(input: &'a str) -> IResult<&str, f64> {
+pub(super) fn realnumber(input: &str) -> IResult<&str, f64> {
     not(alt((
         tag_no_case("NaN"),
         preceded(
@@ -77,7 +77,7 @@ pub(super) fn realnumber<'a>(input: &'a str) -> IResult<&str, f64> {
     nom::number::complete::double(input)
 }
 
-pub(super) fn floatlike<'a>(input: &'a str) -> IResult<&str, f64> {
+pub(super) fn floatlike(input: &str) -> IResult<&str, f64> {
     alt((
         value(f64::NAN, tag_no_case("NaN")),
         value(
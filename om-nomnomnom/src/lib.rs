@@ -7,31 +7,255 @@ use tracing::{debug, error, info, span, trace, warn, Instrument, Level};
 
 use std::collections::HashMap;
 
+use serde_derive::Serialize;
+
 /// Tokenizes an exposition document
 pub mod lexer;
 
 /// Parses the tokens into a more user friendly format and performs additional validation.
 pub mod parser;
 
+/// Selects samples with PromQL-style series selectors.
+pub mod query;
+
+/// Writes parsed metric families back out as an exposition document.
+pub mod serializer;
+
+/// Parses an exposition incrementally, handing off each family as soon as it's complete.
+pub mod stream;
+
 #[cfg(test)]
 mod test;
 
 /// Indicates that an error occurred while processing an exposition document
 #[derive(thiserror::Error, Debug)]
 pub enum OmError {
-    #[error(transparent)]
-    Parse(#[from] parser::ParseError),
+    #[error("{error} at {location}")]
+    Parse {
+        error: parser::ParseError,
+        location: Location,
+    },
+
+    #[error("lexer failed at {location}: {message}")]
+    LexError { message: String, location: Location },
+
+    #[error("couldn't read the exposition: {0}")]
+    Io(#[from] std::io::Error),
 
-    #[error("lexer failed {0}")]
-    LexError(String),
+    #[error("{0:?} expositions aren't supported here")]
+    UnsupportedFormat(Format),
 
     #[error("unknown error")]
     Unknown,
 }
 
+/// The position in an exposition document at which an error was detected
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Location {
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column, counted in characters.  The lexer reports where in the line it stopped,
+    /// the parser the start of the token an error is about, e.g. a sample's value.
+    pub column: usize,
+    /// The text of the offending line
+    pub text: String,
+    /// The name of the [`MetricFamily`](crate::parser::MetricFamily) being built, if any
+    pub family: Option<String>,
+}
+
+impl OmError {
+    /// Where in the exposition the error was detected
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Self::Parse { location, .. } | Self::LexError { location, .. } => Some(location),
+            Self::Io(_) | Self::UnsupportedFormat(_) | Self::Unknown => None,
+        }
+    }
+
+    /// A stable identifier for the kind of error, see [`parser::ParseError::rule_id`].  Any
+    /// lexer error is a `syntax` error.
+    pub fn rule_id(&self) -> &'static str {
+        match self {
+            Self::Parse { error, .. } => error.rule_id(),
+            Self::LexError { .. } => "syntax",
+            Self::Io(_) => "io",
+            Self::UnsupportedFormat(_) => "unsupported-format",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// What went wrong, without where
+    pub fn message(&self) -> String {
+        match self {
+            Self::Parse { error, .. } => error.to_string(),
+            Self::LexError { message, .. } => message.clone(),
+            err => err.to_string(),
+        }
+    }
+}
+
+impl Location {
+    pub(crate) fn new(line: usize, column: usize, text: &str, family: Option<&str>) -> Self {
+        Self {
+            line,
+            column,
+            text: text.to_string(),
+            family: family.map(str::to_string),
+        }
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)?;
+        if let Some(family) = self.family.as_ref() {
+            write!(f, " in «{}»", family)?;
+        }
+        write!(f, ": {:?}", self.text)
+    }
+}
+
 /// Parses an exposition document into a [`HashMap`] containing an entry per [`MetricFamily`](crate::parser::MetricFamily).
 pub fn parse<'a>(data: &'a str) -> Result<HashMap<&'a str, parser::MetricFamily<'a>>, OmError> {
-    let (_, tokens) = lexer::exposition(data).map_err(|e| OmError::LexError(e.to_string()))?;
-    let metric_families = parser::parse(tokens)?;
+    let lines = lexer::exposition(data)?;
+    let metric_families = parser::parse(lines)?;
     Ok(metric_families)
 }
+
+/// Parses an exposition document into a [`MetricSet`](crate::parser::MetricSet), which keeps the
+/// families in the order they appear in the document.
+pub fn parse_metric_set(data: &str) -> Result<parser::MetricSet<'_>, OmError> {
+    let lines = lexer::exposition(data)?;
+    let metric_set = parser::parse_metric_set(lines)?;
+    Ok(metric_set)
+}
+
+/// [`parse_metric_set`] for an exposition held in bytes.  Lines are split and lexed by hand, see
+/// [`lexer::bytes`], but the families are built just as [`parse_metric_set`] builds them: each
+/// sample with labels still gets a [`LabelSet`](crate::parser::LabelSet) of its own.
+pub fn parse_bytes(data: &[u8]) -> Result<parser::MetricSet<'_>, OmError> {
+    let lines = lexer::bytes::exposition(data)?;
+    let metric_set = parser::parse_metric_set(lines)?;
+    Ok(metric_set)
+}
+
+/// [`parse_bytes`] on as many threads as there are cores, see
+/// [`parse_metric_set_parallel`](crate::parser::parse_metric_set_parallel).
+pub fn parse_parallel(data: &[u8]) -> Result<parser::MetricSet<'_>, OmError> {
+    parser::parse_metric_set_parallel(data, 0, &parser::ParserOptions::default())
+}
+
+/// The text format of an exposition document
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Pick a format with [`Format::detect`]
+    #[default]
+    Auto,
+    /// OpenMetrics 1.0
+    OpenMetrics,
+    /// The Prometheus text exposition format, version 0.0.4
+    Prometheus,
+}
+
+impl Format {
+    /// An OpenMetrics exposition must end with `# EOF`, a Prometheus one never does.  One that was
+    /// cut short is still told apart by syntax Prometheus doesn't have: a `# UNIT`, an exemplar,
+    /// an `info`, `stateset` or `gaugehistogram` family, or a counter with `_total` or `_created`
+    /// samples, as Prometheus names a counter's samples after the family.
+    pub fn detect(data: &str) -> Self {
+        if data.trim_end().ends_with("# EOF") {
+            return Self::OpenMetrics;
+        }
+
+        let mut counter = None;
+        for line in data.lines() {
+            let mut words = line.split_whitespace();
+            let is_openmetrics = match (words.next(), words.next(), words.next(), words.next()) {
+                (Some("#"), Some("UNIT"), _, _) => true,
+                (Some("#"), Some("TYPE"), Some(name), Some(metric_type)) => {
+                    counter = (metric_type == "counter").then_some(name);
+                    matches!(metric_type, "info" | "stateset" | "gaugehistogram")
+                }
+                (Some("#"), _, _, _) | (None, _, _, _) => false,
+                (Some(_), _, _, _) => {
+                    let name = line.split(['{', ' ', '\t']).next().unwrap_or_default();
+                    let suffix = counter.and_then(|counter| name.strip_prefix(counter));
+                    line.contains(" # {") || matches!(suffix, Some("_total" | "_created"))
+                }
+            };
+
+            if is_openmetrics {
+                return Self::OpenMetrics;
+            }
+        }
+
+        Self::Prometheus
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "openmetrics" => Ok(Self::OpenMetrics),
+            "prometheus" => Ok(Self::Prometheus),
+            _ => Err(format!("unknown format «{}»", s)),
+        }
+    }
+}
+
+/// Parses a Prometheus text exposition (version 0.0.4) into a [`MetricSet`](crate::parser::MetricSet).
+///
+/// Families are built with the same model as OpenMetrics: `untyped` families become «Unknown»
+/// ones and millisecond timestamps are converted to seconds.
+pub fn parse_prometheus(data: &str) -> Result<parser::MetricSet<'_>, OmError> {
+    let lines = lexer::prometheus::exposition(data)?;
+    let metric_set = parser::parse_prometheus(lines)?;
+    Ok(metric_set)
+}
+
+/// Parses an exposition document in the given [`Format`], detecting it if need be.
+pub fn parse_with_format(data: &str, format: Format) -> Result<parser::MetricSet<'_>, OmError> {
+    parse_with_options(data, &parser::ParserOptions::default().format(format))
+}
+
+/// Parses an exposition document as per `options`, see [`ParserOptions`](crate::parser::ParserOptions).
+pub fn parse_with_options<'a>(
+    data: &'a str,
+    options: &parser::ParserOptions,
+) -> Result<parser::MetricSet<'a>, OmError> {
+    options.parse(data)
+}
+
+/// Parses as much of an exposition document as possible.  Families that fail validation are
+/// skipped, and the errors that caused them to be skipped are returned alongside the rest, ordered
+/// by line.  See [`ParserOptions::parse_lenient`](crate::parser::ParserOptions::parse_lenient).
+pub fn parse_lenient<'a>(
+    data: &'a str,
+    options: &parser::ParserOptions,
+) -> (parser::MetricSet<'a>, Vec<OmError>) {
+    options.parse_lenient(data)
+}
+
+/// Serializes the output of [`parse`] back into an exposition document.
+pub fn serialize(families: &HashMap<&str, parser::MetricFamily>) -> String {
+    serializer::to_string(families)
+}
+
+/// Serializes the output of [`parse_metric_set`] back into an exposition document.
+pub fn serialize_metric_set(metric_set: &parser::MetricSet) -> String {
+    let mut out = String::new();
+    serializer::write_metric_set(&mut out, metric_set).expect("writing to a String can't fail");
+    out
+}
+
+/// Serializes the output of [`parse_metric_set`] into a Prometheus text exposition, see
+/// [`serializer::prometheus::write_family`] for how families are translated.
+pub fn serialize_prometheus(metric_set: &parser::MetricSet) -> String {
+    let mut out = String::new();
+    serializer::prometheus::write_metric_set(&mut out, metric_set)
+        .expect("writing to a String can't fail");
+    out
+}
@@ -2,20 +2,47 @@
 use tracing::{debug, error, info, span, trace, warn, Instrument, Level};
 
 use std::{
-    borrow::{Borrow, Cow},
+    borrow::Cow,
+    cmp::Ordering,
     collections::{HashMap, HashSet},
-    hash::{Hash, Hasher},
+    hash::Hash,
 };
 
-#[cfg(not(feature = "naive_label_hash"))]
-use std::cmp::Ordering;
-
-use itertools::{Itertools, Position};
+use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex::{Captures, Regex};
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use smallvec::SmallVec;
+
+use crate::{lexer, Format, Location, OmError};
+
+/// Typed views over the samples of a [`MetricFamily`].  Each view represents a single MetricPoint:
+/// the samples that share a label set (ignoring the `le`, `quantile`, or state label) and a
+/// timestamp.
+pub mod views;
+
+mod column;
+
+mod label_set;
+pub use label_set::LabelSet;
+
+mod series;
+pub use series::{MetricPoint, Series};
+
+mod metric_set;
+pub use metric_set::MetricSet;
 
-use crate::lexer;
+mod options;
+pub use options::ParserOptions;
+
+mod prometheus;
+pub use prometheus::{parse_prometheus, parse_prometheus_with_options};
+
+mod lenient;
+pub use lenient::{parse_metric_set_lenient, parse_prometheus_lenient};
+
+mod parallel;
+pub use parallel::parse_metric_set_parallel;
 
 // Suffixes for a MetricFamily that could conflict with a valid sample name
 //
@@ -42,61 +69,127 @@ const CONFLICT_SUFFIXES: &[&str] = &[
     "_bucket", "_count", "_created", "_gcount", "_gsum", "_info", "_sum", "_total",
 ];
 
+/// Whether a family called `name` would clash with one `is_known` says was already seen, see
+/// [`CONFLICT_SUFFIXES`].
+fn has_name_conflict<F>(name: &str, is_known: F) -> bool
+where
+    F: Fn(&str) -> bool,
+{
+    CONFLICT_SUFFIXES
+        .iter()
+        .any(|conflict| is_known(&format!("{}{}", name, conflict)))
+}
+
 lazy_static! {
     // Pattern used to check for escape characters
     static ref UNESCAPE_RE: Regex = Regex::new(r#"(\\[n"\\])"#).unwrap();
 }
 
-struct Builder<'a> {
+#[derive(Default)]
+struct Builder<'a, 'k> {
     name: Option<&'a str>,
     help: Option<&'a str>,
     unit: Option<&'a str>,
     metric_type: Option<MetricType>,
     samples: Vec<Sample<'a>>,
-    families: HashMap<&'a str, MetricFamily<'a>>,
+    /// The families completed so far, named after the lines they were parsed from
+    families: Vec<(&'a str, MetricFamily<'a>)>,
+    names: HashSet<&'a str>,
+    /// Families that were already handed off by a [`StreamParser`](crate::stream::StreamParser)
+    known: Option<&'k HashSet<String>>,
+    options: ParserOptions,
     flags: BuilderFlags,
+    /// The line currently being processed
+    line: (usize, &'a str),
+    /// The line the current MetricFamily started on
+    start: (usize, &'a str),
+    /// The last line of the current MetricFamily
+    end: (usize, &'a str),
+    /// The line of each of `samples`, where errors found once the family is complete are reported
+    sample_lines: Vec<(usize, &'a str)>,
+}
+
+/// A rule broken by a complete family, and where to report it
+struct FamilyError<'s, 'a> {
+    error: ParseError,
+    culprit: Culprit<'s, 'a>,
+}
+
+enum Culprit<'s, 'a> {
+    /// The sample that broke the rule
+    Sample(&'s Sample<'a>),
+    /// The family's first line, for rules about its metadata
+    Start,
+    /// The family's last line, for rules about the family as a whole, e.g. a sample it lacks
+    End,
+}
+
+impl<'s, 'a> From<ParseError> for FamilyError<'s, 'a> {
+    fn from(error: ParseError) -> Self {
+        Self {
+            error,
+            culprit: Culprit::End,
+        }
+    }
+}
+
+impl ParseError {
+    fn at<'s, 'a>(self, sample: &'s Sample<'a>) -> FamilyError<'s, 'a> {
+        FamilyError {
+            error: self,
+            culprit: Culprit::Sample(sample),
+        }
+    }
+
+    fn at_start<'s, 'a>(self) -> FamilyError<'s, 'a> {
+        FamilyError {
+            error: self,
+            culprit: Culprit::Start,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 struct BuilderFlags {
     has_inf_bucket: bool,
     has_total_bucket: bool,
-    has_neg_bucket: bool,
     has_bucket: bool,
-    has_count: bool,
-    has_gcount: bool,
-    has_gsum: bool,
-    has_sum: bool,
     has_eof: bool,
 }
 
 /// Exemplars are references to data outside of the MetricSet. A common use case are IDs of program traces.
 ///
 /// Exemplars MUST consist of a LabelSet and a value, and MAY have a timestamp. They MAY each be different from the MetricPoints' LabelSet and timestamp.
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Exemplar<'a> {
-    labels: HashMap<&'a str, Cow<'a, str>>,
-    number: f64,
-    timestamp: Option<f64>,
+    pub labels: LabelSet<'a>,
+    #[serde(with = "non_finite")]
+    pub number: f64,
+    /// When the exemplar was observed, in seconds since the epoch
+    #[serde(default, with = "non_finite::option")]
+    pub timestamp: Option<f64>,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Label<'a> {
-    pub name: &'a str,
+    pub name: Cow<'a, str>,
     pub value: Cow<'a, str>,
 }
 
 /// A MetricFamily is a collection of related (and similarly named) metrics
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct MetricFamily<'a> {
     pub metric_type: MetricType,
     pub help: Option<Cow<'a, str>>,
-    pub unit: Option<&'a str>,
+    pub unit: Option<Cow<'a, str>>,
     pub samples: Vec<Sample<'a>>,
+    /// The 1-based line of the exposition the family starts on
+    #[serde(skip)]
+    pub line: usize,
 }
 
 /// [`MetricFamily`] type.  The default is `Unknown`
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum MetricType {
     /// Counters measure discrete events.
     Counter,
@@ -127,9 +220,18 @@ pub enum ParseError {
     #[error("malformed «Counter» MetricFamily")]
     BadCounter,
 
+    #[error("malformed «_created» sample")]
+    BadCreated,
+
+    #[error("an exemplar's value MUST be within its bucket")]
+    BadExemplar,
+
     #[error("malformed «Histogram» MetricFamily")]
     BadHistogram,
 
+    #[error("«_count» or «_gcount» MUST match the +Inf bucket")]
+    BadHistogramCount,
+
     #[error("malformed «Info» MetricFamily")]
     BadInfo,
 
@@ -139,22 +241,39 @@ pub enum ParseError {
     #[error("malformed quantile sample")]
     BadQuantile,
 
+    #[error("quantile values MUST NOT be negative")]
+    BadQuantileValue,
+
     #[error("malformed «StateSet» MetricFamily")]
     BadStateSet,
 
     #[error("malformed «Summary» MetricFamily")]
     BadSummary,
 
+    #[error("«Summary» count MUST NOT be NaN or negative")]
+    BadSummaryCount,
+
+    #[error("«Summary» sum MUST NOT be NaN or negative")]
+    BadSummarySum,
+
     #[error("invalid sample suffix")]
     BadSuffix,
 
-    #[cfg(feature = "enforce_timestamp_monotonic")]
+    #[error("malformed unit")]
+    BadUnit,
+
     #[error("timestamps must increase monotonically")]
     BadTimestampOutOfOrder,
 
     #[error("duplicate metadata name/type/unit/help")]
     DuplicateMeta,
 
+    #[error("duplicate quantile in a «Summary» MetricPoint")]
+    DuplicateQuantile,
+
+    #[error("bucket values MUST NOT decrease as «le» increases")]
+    DecreasingBucket,
+
     #[error("empty label")]
     EmptyLabel,
 
@@ -164,12 +283,21 @@ pub enum ParseError {
     #[error("interleaved data")]
     Interleave,
 
+    #[error("the exposition MUST end with «# EOF»")]
+    MissingEof,
+
     #[error("missing samples")]
     MissingSample,
 
     #[error("MetricFamily name conflict")]
     NameConflict,
 
+    #[error("«Summary» samples MUST NOT have an «le» label")]
+    SummaryWithLe,
+
+    #[error("«Summary» _count and _sum MUST appear together")]
+    UnpairedSummaryCountSum,
+
     #[cfg(feature = "generic_parse_error")]
     #[error("unknown error")]
     Unknown,
@@ -177,18 +305,18 @@ pub enum ParseError {
 
 pub type Result<T> = std::result::Result<T, ParseError>;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Sample<'a> {
-    pub name: &'a str,
-    pub labels: HashMap<&'a str, Cow<'a, str>>,
-    pub number: f64,
+    pub name: Cow<'a, str>,
+    pub labels: LabelSet<'a>,
+    pub number: MetricNumber,
     pub timestamp: Option<f64>,
     pub exemplar: Option<Exemplar<'a>>,
 
     pub kind: SampleKind,
 }
 
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum SampleKind {
     Other,
     Count,
@@ -196,36 +324,107 @@ pub enum SampleKind {
     Sum,
     GCount,
     GSum,
-    HistogramBucket(f64),
-    Quantile(f64),
+    /// The creation time of a «Counter», «Histogram» or «Summary» MetricPoint
+    Created,
+    HistogramBucket(#[serde(with = "non_finite")] f64),
+    Quantile(#[serde(with = "non_finite")] f64),
+}
+
+/// The value of a [`Sample`].  Integers are kept exact rather than being collapsed into an `f64`,
+/// values are compared numerically regardless of representation.
+#[derive(Clone, Copy, Debug)]
+pub enum MetricNumber {
+    Float(f64),
+    Integer(i64),
+    /// An integer too large to fit in an `i64`
+    Unsigned(u64),
 }
 
-impl<'a> Builder<'a> {
+impl<'a, 'k> Builder<'a, 'k> {
     fn new() -> Self {
         Self::default()
     }
 
+    /// Whether a family has already been seen, either by this builder or by a previous one
+    fn is_known(&self, name: &str) -> bool {
+        self.names.contains(name)
+            || self
+                .known
+                .map(|known| known.contains(name))
+                .unwrap_or(false)
+    }
+
+    fn is_prometheus(&self) -> bool {
+        self.options.format == Format::Prometheus
+    }
+
+    /// Feeds a single lexed line to the builder
+    fn line(mut self, line: lexer::Line<'a>) -> std::result::Result<Self, OmError> {
+        self.line = (line.number, line.text);
+
+        // A descriptor for another name completes the current family, as does a sample that isn't
+        // a member of a Prometheus one
+        let name = match &line.token {
+            lexer::MetricToken::Descriptor(meta) => Some(meta.metric_name()),
+            lexer::MetricToken::Metric(sample)
+                if self.is_prometheus()
+                    && !prometheus::is_member(
+                        self.name,
+                        self.metric_type.as_ref(),
+                        sample.name,
+                    ) =>
+            {
+                Some(sample.name)
+            }
+            _ => None,
+        };
+        if let Some(name) = name {
+            if !self.flags.has_eof && self.name.is_some_and(|current| current != name) {
+                self = self.finalize_family(Some(name))?;
+            }
+        }
+
+        let family = self.name;
+        let is_family_line = matches!(
+            line.token,
+            lexer::MetricToken::Descriptor(_) | lexer::MetricToken::Metric(_)
+        );
+        let is_sample = matches!(line.token, lexer::MetricToken::Metric(_));
+        let mut builder = match line.token {
+            lexer::MetricToken::Descriptor(meta) => self.meta(meta),
+            lexer::MetricToken::Metric(sample) => self.sample(sample),
+            lexer::MetricToken::Eof => self.eof(),
+            lexer::MetricToken::Empty => Ok(self),
+        }
+        .map_err(|error| line_error(error, (line.number, line.text), family))?;
+
+        if is_family_line {
+            builder.end = (line.number, line.text);
+        }
+        if is_sample {
+            builder.sample_lines.push((line.number, line.text));
+        }
+
+        Ok(builder)
+    }
+
     fn name(self, metric_name: &'a str) -> Result<Self> {
         if self.flags.has_eof {
             Err(ParseError::Eof)?
         }
 
+        // A line naming another family has already completed the current one, see `line`
         match self.name {
             None => Ok(Self {
                 name: Some(metric_name),
+                start: self.line,
                 ..self
             }),
-            Some(name) => {
-                if name != metric_name {
-                    self.finalize_family(Some(metric_name))
-                } else {
-                    Ok(self)
-                }
-            }
+            Some(_) => Ok(self),
         }
     }
 
-    fn is_meta_allowable(self) -> Result<Self> {
+    fn check_meta_allowed(self) -> Result<Self> {
         if self.flags.has_eof {
             Err(ParseError::Eof)?
         }
@@ -264,8 +463,30 @@ impl<'a> Builder<'a> {
             Err(ParseError::DuplicateMeta)?
         }
 
+        // The unit is separated from the name by a space even when it's empty
+        let unit = unit.ok_or(ParseError::BadUnit)?;
+
+        if !unit
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+        {
+            Err(ParseError::BadUnit)?
+        }
+
+        // If non-empty, it MUST be a suffix of the MetricFamily name separated by an underscore.
+        if !unit.is_empty() {
+            let family_name = self.name.unwrap_or_default();
+            let is_suffix = family_name
+                .strip_suffix(unit)
+                .map(|name| name.ends_with('_'))
+                .unwrap_or(false);
+            if !is_suffix {
+                Err(ParseError::BadUnit)?
+            }
+        }
+
         Ok(Self {
-            unit: unit.or_else(|| "".into()),
+            unit: Some(unit),
             ..self
         })
     }
@@ -281,17 +502,17 @@ impl<'a> Builder<'a> {
                 metric_type,
             } => self
                 .name(metric_name)?
-                .is_meta_allowable()?
+                .check_meta_allowed()?
                 .metric_type(metric_type.into()),
             lexer::MetricDescriptor::Help {
                 metric_name,
                 help_text,
             } => self
                 .name(metric_name)?
-                .is_meta_allowable()?
+                .check_meta_allowed()?
                 .help_text(help_text),
             lexer::MetricDescriptor::Unit { metric_name, unit } => {
-                self.name(metric_name)?.is_meta_allowable()?.unit(unit)
+                self.name(metric_name)?.check_meta_allowed()?.unit(unit)
             }
         }
     }
@@ -306,7 +527,28 @@ impl<'a> Builder<'a> {
             Some(_) => self,
         };
 
-        let sample: Sample = sample.try_into()?;
+        let mut sample = Sample::from_lexer_unclassified(sample, &builder.options)?;
+
+        // None of the OpenMetrics rules apply to a Prometheus sample
+        if builder.is_prometheus() {
+            sample.kind = prometheus::classify(builder.metric_type.as_ref(), &sample)?;
+            builder.samples.push(sample);
+            return Ok(builder);
+        }
+
+        // Classification would report a bad count as a histogram's, and a bad sum as a counter's
+        if builder.metric_type == Some(MetricType::Summary)
+            && (sample.number.is_nan() || sample.number < 0.)
+        {
+            if sample.name.ends_with("_count") {
+                Err(ParseError::BadSummaryCount)?
+            }
+            if sample.name.ends_with("_sum") {
+                Err(ParseError::BadSummarySum)?
+            }
+        }
+
+        sample.kind = sample.classify()?;
 
         // [Counter] A MetricPoint in a Metric's Counter's Total MAY have an exemplar.
         // [Histogram] Bucket values MAY have exemplars.
@@ -314,51 +556,76 @@ impl<'a> Builder<'a> {
         // [GaugeHistogram] Bucket values can have exemplars.
         // [GaugeHistogram] Each bucket covers the values less and or equal to it, and the value of the exemplar MUST be within this range. Exemplars SHOULD be put into the bucket with the highest value. A bucket MUST NOT have more than one exemplar.
         if sample.exemplar.is_some() {
-            // This is ugly
-            if sample.name.ends_with("_bucket")
-                && (builder.metric_type == Some(MetricType::Histogram)
-                    || builder.metric_type == Some(MetricType::GaugeHistogram))
-            {
-            } else if sample.name.ends_with("_total")
-                && builder.metric_type == Some(MetricType::Counter)
-            {
-            } else {
+            let allowed = match builder.metric_type {
+                Some(MetricType::Histogram | MetricType::GaugeHistogram) => {
+                    sample.name.ends_with("_bucket")
+                }
+                Some(MetricType::Counter) => sample.name.ends_with("_total"),
+                _ => false,
+            };
+            if !allowed {
                 Err(ParseError::BadSuffix)?
             }
         }
 
-        if let Some(family_name) = builder.name.as_ref() {
+        if let Some(family_name) = builder.name {
             match builder.metric_type {
                 Some(MetricType::Info) => {
-                    if !(sample.name.starts_with(family_name) && sample.name.ends_with("_info")) {
-                        Err(ParseError::BadInfo)?
-                    } else if !sample.labels.contains_key(family_name) {
-                        Err(ParseError::BadInfo)?
-                    } else if sample.number != 1. {
-                        // The Sample value MUST always be 1.
+                    let named = sample.name.starts_with(family_name)
+                        && sample.name.ends_with("_info")
+                        && sample.labels.contains_key(family_name);
+                    // The Sample value MUST always be 1.
+                    if !named || sample.number != 1. {
                         Err(ParseError::BadInfo)?
                     }
                 }
                 Some(MetricType::StateSet) => {
-                    if &sample.name != family_name || !sample.labels.contains_key(family_name) {
-                        Err(ParseError::BadStateSet)?
-                    } else if sample.number != 1. && sample.number != 0. {
-                        // The State sample's value MUST be 1 if the State is true and MUST be 0 if the State is false.
+                    let named =
+                        sample.name == family_name && sample.labels.contains_key(family_name);
+                    // The State sample's value MUST be 1 if the State is true and MUST be 0 if the State is false.
+                    if !named || (sample.number != 1. && sample.number != 0.) {
                         Err(ParseError::BadStateSet)?
                     }
                 }
                 Some(MetricType::Summary) => {
-                    if !sample.name.ends_with("_count")
-                        && !sample.name.ends_with("_sum")
-                        && !sample.name.ends_with("_created")
-                        && !(&sample.name == family_name && sample.labels.contains_key("quantile"))
-                    {
+                    // The «le» label is reserved for histograms
+                    if sample.labels.contains_key("le") {
+                        Err(ParseError::SummaryWithLe)?
+                    }
+
+                    let allowed = sample.name.ends_with("_count")
+                        || sample.name.ends_with("_sum")
+                        || sample.name.ends_with("_created")
+                        || (sample.name == family_name && sample.labels.contains_key("quantile"));
+                    if !allowed {
                         Err(ParseError::BadSummary)?
                     }
                 }
                 _ => {}
             }
 
+            // Only counters, histograms and summaries have a «_created» sample, anything else
+            // whose name happens to end with the suffix is an ordinary sample.
+            if sample.kind == SampleKind::Created {
+                let is_created = sample
+                    .name
+                    .strip_prefix(family_name)
+                    .map(|suffix| suffix == "_created")
+                    .unwrap_or(false);
+                match builder.metric_type {
+                    // The created timestamp is in seconds since the epoch
+                    Some(MetricType::Counter | MetricType::Histogram | MetricType::Summary)
+                        if is_created =>
+                    {
+                        if !sample.number.as_f64().is_finite() {
+                            Err(ParseError::BadCreated)?
+                        }
+                    }
+                    _ if sample.name == family_name => sample.kind = SampleKind::Other,
+                    _ => Err(ParseError::BadCreated)?,
+                }
+            }
+
             match sample.kind {
                 SampleKind::HistogramBucket(threshold) => {
                     builder.flags.has_bucket = true;
@@ -368,34 +635,15 @@ impl<'a> Builder<'a> {
                         builder.flags.has_inf_bucket = true;
                     } else {
                         trace!("is {}", threshold);
-                        if threshold < 0. {
-                            builder.flags.has_neg_bucket = true;
-                        }
                     }
                 }
-                SampleKind::Count => builder.flags.has_count = true,
+                SampleKind::Count => {}
                 SampleKind::Total => builder.flags.has_total_bucket = true,
-                SampleKind::GCount => builder.flags.has_gcount = true,
-                SampleKind::Sum => {
-                    if let Some(MetricType::Summary) = builder.metric_type && sample.number < 0. {
-                        Err(ParseError::BadCounter)?
-                    } else if builder.flags.has_neg_bucket {
-                        Err(ParseError::BadHistogram)?
-                    }
-                    builder.flags.has_sum = true;
-                }
-                SampleKind::GSum => {
-                    info!(flags=?builder.flags, number=sample.number);
-                    if sample.number < 0. && !builder.flags.has_neg_bucket {
-                        Err(ParseError::BadCounter)?
-                    }
-
-                    builder.flags.has_gsum = true;
-                }
+                SampleKind::GCount | SampleKind::Sum | SampleKind::GSum => {}
                 _ => {
                     let is_quantile = sample.labels.contains_key("quantile");
                     if is_quantile && sample.number < 0. {
-                        Err(ParseError::BadCounter)?
+                        Err(ParseError::BadQuantileValue)?
                     }
                 }
             }
@@ -416,41 +664,235 @@ impl<'a> Builder<'a> {
         })
     }
 
-    fn finalize_family(mut self, name: Option<&'a str>) -> Result<Self> {
-        #[cfg(feature = "no_interleave_metric")]
-        {
+    fn finalize_family(mut self, name: Option<&'a str>) -> std::result::Result<Self, OmError> {
+        // Nothing to finalize, e.g. an exposition that's just an EOF
+        let family_name = match self.name {
+            Some(family_name) => family_name,
+            None => return Ok(self),
+        };
+
+        let validated = match self.is_prometheus() {
+            true => prometheus::validate_family(self.metric_type.as_ref(), &self.samples)
+                .map_err(FamilyError::from),
+            false => self.validate_family(family_name),
+        };
+        if let Err(error) = validated {
+            Err(self.family_error(error))?
+        }
+
+        if self.is_known(family_name) {
+            Err(self.family_error(ParseError::Interleave.at_start()))?
+        }
+
+        let help = match self.is_prometheus() {
+            true => self.help.map(prometheus::unescape_help),
+            false => self.help.map(unescape_string),
+        };
+        let family = MetricFamily {
+            metric_type: self.metric_type.unwrap_or(MetricType::Unknown),
+            help,
+            unit: self.unit.map(Cow::Borrowed),
+            samples: self.samples,
+            line: self.start.0,
+        };
+
+        self.families.push((family_name, family));
+        self.names.insert(family_name);
+
+        Ok(Self {
+            families: self.families,
+            names: self.names,
+            known: self.known,
+            options: self.options,
+            name,
+            line: self.line,
+            start: self.line,
+            ..Self::default()
+        })
+    }
+
+    /// Reports an error found once the family is complete at the line of whatever caused it
+    fn family_error(&self, error: FamilyError) -> OmError {
+        let line = match error.culprit {
+            Culprit::Sample(sample) => self
+                .samples
+                .iter()
+                .position(|other| std::ptr::eq(other, sample))
+                .map_or(self.end, |index| self.sample_lines[index]),
+            Culprit::Start => self.start,
+            Culprit::End => self.end,
+        };
+
+        line_error(error.error, line, self.name)
+    }
+
+    /// Checks a complete OpenMetrics family against the rules that span several samples
+    fn validate_family(&self, family_name: &str) -> std::result::Result<(), FamilyError<'_, 'a>> {
+        // A MetricPoint has at most one created timestamp
+        self.samples
+            .iter()
+            .filter(|sample| sample.kind == SampleKind::Created)
+            .try_fold(HashSet::new(), |mut acc, sample| {
+                let point = (&sample.labels, sample.timestamp.map(f64::to_bits));
+                match acc.insert(point) {
+                    true => Ok(acc),
+                    false => Err(ParseError::BadCreated.at(sample)),
+                }
+            })?;
+
+        if self.options.no_interleave_metric {
+            let metric_type = self.metric_type.as_ref().unwrap_or(&MetricType::Unknown);
             self.samples.iter().try_fold(
                 (None, HashSet::with_capacity(self.samples.len())),
-                |(last_labelset, mut acc), sample| {
-                    let key = sample.labelset();
+                |(last_series, mut acc), sample| {
+                    let key = series::SeriesKey::new(metric_type, sample);
 
-                    if let Some(labelset) = last_labelset && labelset != key && acc.contains(&key) {
-                        Err(ParseError::Interleave)
+                    if matches!(last_series, Some(series) if series != key) && acc.contains(&key) {
+                        Err(ParseError::Interleave.at(sample))
                     } else {
-                        acc.insert(key.clone());
+                        acc.insert(key);
                         Ok((Some(key), acc))
                     }
                 },
             )?;
         }
 
-        #[cfg(feature = "enforce_timestamp_monotonic")]
+        // The name of a MetricFamily MUST NOT result in a potential clash for sample metric names
+        // as per the ABNF with another MetricFamily in the Text Format within a MetricSet. An
+        // example would be a gauge called "foo_created" as a counter called "foo" could create a
+        // "foo_created" in the text format.
+        if has_name_conflict(family_name, |name| self.is_known(name)) {
+            Err(ParseError::NameConflict.at_start())?;
+        }
+
+        // Info and StateSet MetricFamilies MUST have an empty Unit.
+        if matches!(
+            self.metric_type,
+            Some(MetricType::Info | MetricType::StateSet)
+        ) && !self.unit.unwrap_or_default().is_empty()
         {
+            Err(ParseError::BadUnit.at_start())?
+        }
+
+        match self.metric_type {
+            Some(MetricType::Histogram) => {
+                if !self.flags.has_bucket {
+                    Err(ParseError::BadHistogram)?
+                }
+
+                for (_, _, samples) in series::group(&MetricType::Histogram, &self.samples) {
+                    let sum = samples.iter().find(|sample| sample.kind == SampleKind::Sum);
+                    let count = samples
+                        .iter()
+                        .find(|sample| sample.kind == SampleKind::Count);
+                    let has_neg_bucket = samples.iter().any(|sample| {
+                        matches!(sample.kind, SampleKind::HistogramBucket(threshold) if threshold < 0.)
+                    });
+
+                    // If and only if a Sum Value is present in a MetricPoint, then the
+                    // MetricPoint's +Inf Bucket value MUST also appear in a Sample with a
+                    // MetricName with the suffix "_count".
+                    if let Some(unpaired) = unpaired(sum, count) {
+                        Err(ParseError::BadHistogram.at(unpaired))?
+                    }
+
+                    // Sum is only a counter, and so only allowed, if there are no negative buckets
+                    match sum {
+                        Some(sum) if has_neg_bucket => Err(ParseError::BadHistogram.at(sum))?,
+                        Some(sum) if sum.number < 0. => Err(ParseError::BadCounter.at(sum))?,
+                        _ => {}
+                    }
+
+                    validate_buckets(&samples, SampleKind::Count, &self.options)?;
+                }
+            }
+            Some(MetricType::GaugeHistogram) => {
+                if !self.flags.has_bucket || !self.flags.has_inf_bucket {
+                    Err(ParseError::BadHistogram)?
+                }
+
+                for (_, _, samples) in series::group(&MetricType::GaugeHistogram, &self.samples) {
+                    let gsum = samples
+                        .iter()
+                        .find(|sample| sample.kind == SampleKind::GSum);
+                    let gcount = samples
+                        .iter()
+                        .find(|sample| sample.kind == SampleKind::GCount);
+                    let has_neg_bucket = samples.iter().any(|sample| {
+                        matches!(sample.kind, SampleKind::HistogramBucket(threshold) if threshold < 0.)
+                    });
+
+                    // A MetricPoint has both a GCount and a GSum, or neither
+                    if let Some(unpaired) = unpaired(gsum, gcount) {
+                        Err(ParseError::BadHistogram.at(unpaired))?
+                    }
+
+                    // GSum may only be negative if there are negative buckets
+                    match gsum {
+                        Some(gsum) if gsum.number < 0. && !has_neg_bucket => {
+                            Err(ParseError::BadCounter.at(gsum))?
+                        }
+                        _ => {}
+                    }
+
+                    validate_buckets(&samples, SampleKind::GCount, &self.options)?;
+                }
+            }
+            Some(MetricType::Summary) => {
+                for (_, _, samples) in series::group(&MetricType::Summary, &self.samples) {
+                    // Each quantile of a MetricPoint MUST only appear once
+                    samples
+                        .iter()
+                        .filter_map(|sample| match sample.kind {
+                            SampleKind::Quantile(quantile) => Some((quantile, *sample)),
+                            _ => None,
+                        })
+                        .try_fold(HashSet::new(), |mut acc, (quantile, sample)| {
+                            match acc.insert(quantile.to_bits()) {
+                                true => Ok(acc),
+                                false => Err(ParseError::DuplicateQuantile.at(sample)),
+                            }
+                        })?;
+
+                    let count = samples
+                        .iter()
+                        .find(|sample| sample.kind == SampleKind::Count);
+                    let sum = samples.iter().find(|sample| sample.kind == SampleKind::Sum);
+                    if let Some(unpaired) = unpaired(count, sum) {
+                        Err(ParseError::UnpairedSummaryCountSum.at(unpaired))?
+                    }
+                }
+            }
+            Some(MetricType::Counter)
+                if !self.samples.is_empty() && !self.flags.has_total_bucket =>
+            {
+                Err(ParseError::BadCounter)?
+            }
+            _ => {}
+        }
+
+        // Checked last, so that a sample breaking a rule of its type, e.g. a duplicate quantile,
+        // isn't reported as out of order
+        if self.options.enforce_timestamp_monotonic {
             self.samples.iter().try_fold(
                 (None, None),
-                |(cur_id, cur_timestamp): (Option<String>, Option<f64>), sample| {
-                    let new_id = format!("{}:{}", sample.name, sample.labelset());
+                |(cur_id, cur_timestamp): (Option<(&str, &LabelSet)>, Option<f64>), sample| {
+                    let new_id = (sample.name.as_ref(), &sample.labels);
                     match (cur_id, new_id) {
                         (None, new_id) => Ok((Some(new_id), sample.timestamp)),
                         (Some(cur_id), new_id) if cur_id != new_id => {
                             Ok((Some(new_id), sample.timestamp))
                         }
                         (cur_id, _) => match (cur_timestamp, sample.timestamp) {
-                            (None, _new_timestamp) => Err(ParseError::BadTimestampOutOfOrder),
-                            (Some(_cur_timestamp), None) => Err(ParseError::BadTimestampOutOfOrder),
+                            (None, _new_timestamp) => {
+                                Err(ParseError::BadTimestampOutOfOrder.at(sample))
+                            }
+                            (Some(_cur_timestamp), None) => {
+                                Err(ParseError::BadTimestampOutOfOrder.at(sample))
+                            }
                             (Some(cur_timestamp), Some(new_timestamp)) => {
                                 if new_timestamp < cur_timestamp {
-                                    Err(ParseError::BadTimestampOutOfOrder)
+                                    Err(ParseError::BadTimestampOutOfOrder.at(sample))
                                 } else {
                                     Ok((cur_id, Some(new_timestamp)))
                                 }
@@ -461,158 +903,177 @@ impl<'a> Builder<'a> {
             )?;
         }
 
-        // The name of a MetricFamily MUST NOT result in a potential clash for sample metric names
-        // as per the ABNF with another MetricFamily in the Text Format within a MetricSet. An
-        // example would be a gauge called "foo_created" as a counter called "foo" could create a
-        // "foo_created" in the text format.
-        if let Some(family_name) = self.name.as_ref() {
-            for conflict in CONFLICT_SUFFIXES.iter() {
-                let key = format!("{}{}", family_name, conflict);
-                if self.families.contains_key(key.as_str()) {
-                    Err(ParseError::NameConflict)?;
-                }
-            }
+        Ok(())
+    }
+
+    /// Completes the last family.  `last` is the exposition's last line that isn't empty, where
+    /// a missing EOF is reported.
+    fn finalize(
+        self,
+        last: (usize, &str),
+    ) -> std::result::Result<Vec<(&'a str, MetricFamily<'a>)>, OmError> {
+        // Only OpenMetrics ends with an EOF
+        if !self.flags.has_eof && !self.is_prometheus() {
+            Err(missing_eof(last))?
         }
 
-        match self.metric_type {
-            Some(MetricType::Histogram) => {
-                if !self.flags.has_bucket {
-                    Err(ParseError::BadHistogram)?
-                } else if !self.flags.has_inf_bucket {
-                    Err(ParseError::BadHistogram)?
-                } else if self.flags.has_neg_bucket && self.flags.has_sum {
-                    Err(ParseError::BadHistogram)?
-                } else if (self.flags.has_sum && !self.flags.has_count)
-                    || (!self.flags.has_sum && self.flags.has_count)
-                {
-                    Err(ParseError::BadHistogram)?
-                } else if self.flags.has_count {
-                    // If and only if a Sum Value is present in a MetricPoint, then the
-                    // MetricPoint's +Inf Bucket value MUST also appear in a Sample with a
-                    // MetricName with the suffix "_count".
-                    #[cfg(feature = "validate_histogram_count")]
-                    {
-                        let counts = self
-                            .samples
-                            .iter()
-                            .filter(|sample| 
-                                sample.kind == SampleKind::HistogramBucket(f64::INFINITY) ||
-                                sample.kind == SampleKind::Count
-                            )
-                            .map(|sample| sample.number)
-                            .collect_vec();
-                        if counts.len() != 2 || counts[0] != counts[1] {
-                            Err(ParseError::BadHistogram)?
-                        }
-                    }
-                }
+        self.finalize_family(None).map(|builder| builder.families)
+    }
+}
 
-                let bucket_it = self.samples.iter().filter_map(|sample| match sample.kind {
-                    SampleKind::HistogramBucket(_) => Some(sample),
-                    _ => None,
-                });
+/// Whichever of two samples that go together is there without the other
+fn unpaired<'s, 'a>(
+    a: Option<&&'s Sample<'a>>,
+    b: Option<&&'s Sample<'a>>,
+) -> Option<&'s Sample<'a>> {
+    match (a, b) {
+        (Some(a), None) => Some(*a),
+        (None, Some(b)) => Some(*b),
+        _ => None,
+    }
+}
 
-                // Semantically, Sum, and buckets values are counters so MUST NOT be NaN or negative.
-                bucket_it.clone().try_fold(0., |acc, sample| {
-                    if sample.number < acc {
-                        Err(ParseError::BadCounter)
-                    } else {
-                        Ok(sample.number)
-                    }
-                })?;
+/// A missing EOF is reported right after the exposition's last line that isn't empty
+pub(crate) fn missing_eof((line, text): (usize, &str)) -> OmError {
+    OmError::Parse {
+        error: ParseError::MissingEof,
+        location: Location::new(line, text.chars().count() + 1, text, None),
+    }
+}
 
-                // Buckets MUST be sorted in number increasing order of "le", and the value of the
-                // "le" label MUST follow the rules for Canonical Numbers.
-                // .with_position()
-                bucket_it
-                    .clone()
-                    .filter_map(|sample| {
-                        if let SampleKind::HistogramBucket(threshold) = sample.kind {
-                            Some(threshold)
-                        } else {
-                            None
-                        }
-                    })
-                    .with_position()
-                    .try_fold(0., |acc, sample| match sample {
-                        Position::First(threshold) => Ok(threshold),
-                        Position::Only(threshold) | Position::Last(threshold) => {
-                            match threshold.is_infinite() {
-                                true => Ok(threshold),
-                                false => Err(ParseError::BadBucketOrder),
-                            }
-                        }
-                        Position::Middle(threshold) => {
-                            if threshold <= acc {
-                                Err(ParseError::BadBucketOrder)
-                            } else {
-                                Ok(threshold)
-                            }
-                        }
-                    })?;
-            }
-            Some(MetricType::GaugeHistogram) => {
-                if !self.flags.has_bucket {
-                    Err(ParseError::BadHistogram)?
-                } else if !self.flags.has_inf_bucket {
-                    Err(ParseError::BadHistogram)?
-                } else if self.flags.has_gcount != self.flags.has_gsum {
-                    Err(ParseError::BadHistogram)?
-                }
-            }
-            Some(MetricType::Counter) => {
-                if !self.samples.is_empty() && !self.flags.has_total_bucket {
-                    Err(ParseError::BadCounter)?
-                }
+/// An error reported on `line`, at the start of the token it's about
+pub(super) fn line_error(
+    error: ParseError,
+    (line, text): (usize, &str),
+    family: Option<&str>,
+) -> OmError {
+    OmError::Parse {
+        location: Location::new(line, column::column(text, &error), text, family),
+        error,
+    }
+}
+
+type Group<'a> = (Option<&'a str>, Vec<lexer::Line<'a>>);
+
+/// Splits lexed lines into the lines of each family, using the same rules as the builder: a
+/// descriptor for another name starts a new family, as does a sample that `is_member` says isn't
+/// one of the current family's given its type.  Each group comes with the name of its family, if
+/// any of its lines named it.
+pub(super) fn split_families<'a, F>(lines: Vec<lexer::Line<'a>>, is_member: F) -> Vec<Group<'a>>
+where
+    F: Fn(Option<&str>, Option<&MetricType>, &str) -> bool,
+{
+    let mut groups: Vec<Group> = vec![];
+    let mut metric_type = None;
+
+    for line in lines {
+        let current = groups.last().and_then(|(name, _)| *name);
+        let name = match &line.token {
+            lexer::MetricToken::Descriptor(meta) => Some(meta.metric_name()),
+            lexer::MetricToken::Metric(sample)
+                if !is_member(current, metric_type.as_ref(), sample.name) =>
+            {
+                Some(sample.name)
             }
-            _ => {}
+            _ => current,
+        };
+
+        if groups.is_empty() || (current.is_some() && name != current) {
+            groups.push((name, vec![]));
+            metric_type = None;
         }
 
-        let family = MetricFamily {
-            metric_type: self.metric_type.unwrap_or(MetricType::Unknown),
-            help: self.help.map(unescape_string),
-            unit: self.unit,
-            samples: self.samples,
-        };
+        if let lexer::MetricToken::Descriptor(lexer::MetricDescriptor::Type {
+            metric_type: lexed,
+            ..
+        }) = &line.token
+        {
+            metric_type = metric_type.or_else(|| Some(lexed.clone().into()));
+        }
 
-        self.families.insert(self.name.expect("our name"), family);
+        let group = groups.last_mut().expect("a group was just pushed");
+        group.0 = group.0.or(name);
+        group.1.push(line);
+    }
 
-        Ok(Self {
-            families: self.families,
-            name,
-            ..Self::default()
-        })
+    groups
+}
+
+impl<'a> MetricFamily<'a> {
+    /// Copies whatever is still borrowed from the exposition so the family can outlive it, e.g.
+    /// to be sent to another thread.
+    pub fn into_owned(self) -> MetricFamily<'static> {
+        MetricFamily {
+            metric_type: self.metric_type,
+            help: self.help.map(owned),
+            unit: self.unit.map(owned),
+            samples: self.samples.into_iter().map(Sample::into_owned).collect(),
+            line: self.line,
+        }
     }
+}
 
-    fn finalize(self) -> Result<HashMap<&'a str, MetricFamily<'a>>> {
-        if !self.flags.has_eof {
-            Err(ParseError::Eof)?
+impl<'a> Sample<'a> {
+    /// Copies whatever is still borrowed from the exposition.  See [`MetricFamily::into_owned`].
+    pub fn into_owned(self) -> Sample<'static> {
+        Sample {
+            name: owned(self.name),
+            labels: self.labels.into_owned(),
+            number: self.number,
+            timestamp: self.timestamp,
+            exemplar: self.exemplar.map(Exemplar::into_owned),
+            kind: self.kind,
         }
+    }
+}
+
+impl<'a> Exemplar<'a> {
+    /// Copies whatever is still borrowed from the exposition.  See [`MetricFamily::into_owned`].
+    pub fn into_owned(self) -> Exemplar<'static> {
+        Exemplar {
+            labels: self.labels.into_owned(),
+            number: self.number,
+            timestamp: self.timestamp,
+        }
+    }
+
+    /// The ID of the trace the exemplar links to, from its `trace_id` label
+    pub fn trace_id(&self) -> Option<&str> {
+        self.labels.get("trace_id")
+    }
 
-        Ok(self.finalize_family(None)?.families)
+    /// The ID of the span the exemplar links to, from its `span_id` label
+    pub fn span_id(&self) -> Option<&str> {
+        self.labels.get("span_id")
     }
 }
 
-impl<'a> Default for Builder<'a> {
-    fn default() -> Self {
-        Self {
-            name: None,
-            help: None,
-            unit: None,
-            metric_type: None,
-            samples: vec![],
-            families: HashMap::new(),
-            flags: BuilderFlags::default(),
+impl<'a> Label<'a> {
+    /// Copies whatever is still borrowed from the exposition.  See [`MetricFamily::into_owned`].
+    pub fn into_owned(self) -> Label<'static> {
+        Label {
+            name: owned(self.name),
+            value: owned(self.value),
         }
     }
 }
 
+fn owned(s: Cow<str>) -> Cow<'static, str> {
+    Cow::Owned(s.into_owned())
+}
+
 impl<'a> TryFrom<lexer::Exemplar<'a>> for Exemplar<'a> {
     type Error = ParseError;
 
     fn try_from(l: lexer::Exemplar<'a>) -> Result<Self> {
+        Self::from_lexer(l, &ParserOptions::default())
+    }
+}
+
+impl<'a> Exemplar<'a> {
+    fn from_lexer(l: lexer::Exemplar<'a>, options: &ParserOptions) -> Result<Self> {
         Ok(Self {
-            labels: Label::from_lexer_labels(l.labels)?,
+            labels: Label::from_lexer_labels(l.labels, options)?,
             number: l.number,
             timestamp: l.timestamp,
         })
@@ -620,28 +1081,14 @@ impl<'a> TryFrom<lexer::Exemplar<'a>> for Exemplar<'a> {
 }
 
 impl<'a> Label<'a> {
-    fn from_lexer_labels(l: Vec<lexer::Label<'a>>) -> Result<HashMap<&'a str, Cow<'a, str>>> {
+    fn from_lexer_labels(l: lexer::Labels<'a>, options: &ParserOptions) -> Result<LabelSet<'a>> {
         let l = l
             .into_iter()
-            .map(|l| Label::try_from(l))
-            .filter(|l| {
-                if let Err(ParseError::EmptyLabel) = l {
-                    false
-                } else {
-                    true
-                }
-            })
-            .try_fold(HashMap::new(), |mut acc, label| {
-                let label = label?;
-                if acc.contains_key(label.name) {
-                    Err(ParseError::DuplicateMeta)
-                } else {
-                    acc.insert(label.name, label.value);
-                    Ok(acc)
-                }
-            })?;
+            .map(|label| Label::from_lexer(label, options))
+            .filter(|l| !matches!(l, Err(ParseError::EmptyLabel)))
+            .collect::<Result<SmallVec<[_; 4]>>>()?;
 
-        Ok(l)
+        LabelSet::try_from_labels(l)
     }
 }
 
@@ -649,28 +1096,21 @@ impl<'a> TryFrom<lexer::Label<'a>> for Label<'a> {
     type Error = ParseError;
 
     fn try_from(l: lexer::Label<'a>) -> Result<Self> {
+        Self::from_lexer(l, &ParserOptions::default())
+    }
+}
+
+impl<'a> Label<'a> {
+    fn from_lexer(l: lexer::Label<'a>, options: &ParserOptions) -> Result<Self> {
         match l.value {
             Some(value) => {
-                #[cfg(feature = "naive_wide_char_support")]
-                {
-                    let total_length = l.name.len() + value.len();
-                    if l.name.len() + value.len() > 256 {
-                        error!(name_len = l.name.len(), value_len = value.len());
-                        Err(ParseError::BadLabelTooLong)?
-                    }
-                }
-
-                #[cfg(not(feature = "naive_wide_char_support"))]
-                {
-                    let total_length = l.name.len() + value.chars().count();
-                    if total_length > 128 {
-                        error!(name_len = l.name.len(), value_len = value.len());
-                        Err(ParseError::BadLabelTooLong)?
-                    }
+                if !options.is_label_length_allowed(l.name, value) {
+                    error!(name_len = l.name.len(), value_len = value.len());
+                    Err(ParseError::BadLabelTooLong)?
                 }
 
                 Ok(Self {
-                    name: l.name,
+                    name: Cow::Borrowed(l.name),
                     value: unescape_string(value),
                 })
             }
@@ -694,33 +1134,321 @@ impl From<lexer::MetricType> for MetricType {
     }
 }
 
-impl<'a> Sample<'a> {
-    fn labelset(&self) -> u64 {
-        #[cfg(not(feature = "hash_fnv"))]
-        let mut hasher = DefaultHasher::new();
-
-        #[cfg(feature = "hash_fnv")]
-        let mut hasher = fnv::FnvHasher::default();
-
-        #[cfg(not(feature = "naive_label_hash"))]
-        let it = {
-            let mut key = self.labels.iter().collect::<Vec<_>>();
-            key.sort_by(|a, b| match a.0.cmp(b.0) {
-                Ordering::Equal => a.1.cmp(b.1),
-                other => other,
-            });
-            key
-        };
+impl MetricNumber {
+    /// The value as an `f64`.  Integers beyond 2^53 may lose precision.
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            Self::Float(n) => n,
+            Self::Integer(n) => n as f64,
+            Self::Unsigned(n) => n as f64,
+        }
+    }
+
+    /// The value as an `i64`, if it can be represented exactly
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Self::Float(n) if n.fract() == 0. && n >= i64::MIN as f64 && n < i64::MAX as f64 => {
+                Some(n as i64)
+            }
+            Self::Float(_) => None,
+            Self::Integer(n) => Some(n),
+            Self::Unsigned(n) => i64::try_from(n).ok(),
+        }
+    }
+
+    /// The value as a `u64`, if it can be represented exactly
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Self::Float(n) if n.fract() == 0. && n >= 0. && n < u64::MAX as f64 => Some(n as u64),
+            Self::Float(_) => None,
+            Self::Integer(n) => u64::try_from(n).ok(),
+            Self::Unsigned(n) => Some(n),
+        }
+    }
+
+    /// Returns `true` for integers and for floats without a fractional part
+    pub fn is_integer(&self) -> bool {
+        match *self {
+            Self::Float(n) => n.is_finite() && n.fract() == 0.,
+            Self::Integer(_) | Self::Unsigned(_) => true,
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        matches!(self, Self::Float(n) if n.is_nan())
+    }
+
+    pub fn is_infinite(&self) -> bool {
+        matches!(self, Self::Float(n) if n.is_infinite())
+    }
+
+    fn as_i128(&self) -> Option<i128> {
+        match *self {
+            Self::Float(_) => None,
+            Self::Integer(n) => Some(n.into()),
+            Self::Unsigned(n) => Some(n.into()),
+        }
+    }
+}
 
-        #[cfg(feature = "naive_label_hash")]
-        let it = self.labels.iter();
+impl PartialEq for MetricNumber {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
 
-        for k in it {
-            k.0.hash(&mut hasher);
-            k.1.hash(&mut hasher);
+impl PartialOrd for MetricNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self.as_i128(), other.as_i128()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => self.as_f64().partial_cmp(&other.as_f64()),
+        }
+    }
+}
+
+/// Integers are subtracted exactly.  The difference is a float if either side is one, or if it
+/// fits in neither an `i64` nor a `u64`.
+impl std::ops::Sub for MetricNumber {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        match (self.as_i128(), other.as_i128()) {
+            (Some(a), Some(b)) => {
+                let difference = a - b;
+                match (i64::try_from(difference), u64::try_from(difference)) {
+                    (Ok(n), _) => Self::Integer(n),
+                    (_, Ok(n)) => Self::Unsigned(n),
+                    _ => Self::Float(difference as f64),
+                }
+            }
+            _ => Self::Float(self.as_f64() - other.as_f64()),
         }
+    }
+}
 
-        hasher.finish()
+impl PartialEq<f64> for MetricNumber {
+    fn eq(&self, other: &f64) -> bool {
+        self.as_f64() == *other
+    }
+}
+
+impl PartialOrd<f64> for MetricNumber {
+    fn partial_cmp(&self, other: &f64) -> Option<Ordering> {
+        self.as_f64().partial_cmp(other)
+    }
+}
+
+impl From<f64> for MetricNumber {
+    fn from(n: f64) -> Self {
+        Self::Float(n)
+    }
+}
+
+impl From<i64> for MetricNumber {
+    fn from(n: i64) -> Self {
+        Self::Integer(n)
+    }
+}
+
+impl From<u64> for MetricNumber {
+    fn from(n: u64) -> Self {
+        match i64::try_from(n) {
+            Ok(n) => Self::Integer(n),
+            Err(_) => Self::Unsigned(n),
+        }
+    }
+}
+
+impl From<lexer::MetricNumber> for MetricNumber {
+    fn from(l: lexer::MetricNumber) -> Self {
+        match l {
+            lexer::MetricNumber::Float(n) => Self::Float(n),
+            lexer::MetricNumber::Integer(n) => Self::Integer(n),
+            lexer::MetricNumber::Unsigned(n) => Self::Unsigned(n),
+        }
+    }
+}
+
+impl std::fmt::Display for MetricNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Float(n) => f.write_str(&crate::serializer::format_number(*n)),
+            Self::Integer(n) => write!(f, "{}", n),
+            Self::Unsigned(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl serde::Serialize for MetricNumber {
+    fn serialize<S>(&self, s: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::Float(n) => non_finite::serialize(n, s),
+            Self::Integer(n) => s.serialize_i64(*n),
+            Self::Unsigned(n) => s.serialize_u64(*n),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for MetricNumber {
+    fn deserialize<D>(d: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = MetricNumber;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a number, \"NaN\", \"+Inf\" or \"-Inf\"")
+            }
+
+            fn visit_i64<E>(self, n: i64) -> std::result::Result<Self::Value, E> {
+                Ok(MetricNumber::Integer(n))
+            }
+
+            fn visit_u64<E>(self, n: u64) -> std::result::Result<Self::Value, E> {
+                Ok(match i64::try_from(n) {
+                    Ok(n) => MetricNumber::Integer(n),
+                    Err(_) => MetricNumber::Unsigned(n),
+                })
+            }
+
+            fn visit_f64<E>(self, n: f64) -> std::result::Result<Self::Value, E> {
+                Ok(MetricNumber::Float(n))
+            }
+
+            fn visit_str<E>(self, s: &str) -> std::result::Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                non_finite::from_str(s)
+                    .map(MetricNumber::Float)
+                    .ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(s), &self))
+            }
+        }
+
+        d.deserialize_any(Visitor)
+    }
+}
+
+/// (De)serializes floats, spelling out the values JSON can't represent as they're written in an
+/// exposition.
+mod non_finite {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S>(n: &f64, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if n.is_nan() {
+            s.serialize_str("NaN")
+        } else if n.is_infinite() && n.is_sign_positive() {
+            s.serialize_str("+Inf")
+        } else if n.is_infinite() && n.is_sign_negative() {
+            s.serialize_str("-Inf")
+        } else {
+            s.serialize_f64(*n)
+        }
+    }
+
+    pub(super) fn deserialize<'de, D>(d: D) -> Result<f64, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match super::MetricNumber::deserialize(d)? {
+            super::MetricNumber::Float(n) => Ok(n),
+            n => Ok(n.as_f64()),
+        }
+    }
+
+    pub(super) fn from_str(s: &str) -> Option<f64> {
+        match s {
+            "NaN" => Some(f64::NAN),
+            "+Inf" => Some(f64::INFINITY),
+            "-Inf" => Some(f64::NEG_INFINITY),
+            _ => None,
+        }
+    }
+
+    pub(super) mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub(in super::super) fn serialize<S>(n: &Option<f64>, s: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match n {
+                Some(n) => super::serialize(n, s),
+                None => s.serialize_none(),
+            }
+        }
+
+        pub(in super::super) fn deserialize<'de, D>(d: D) -> Result<Option<f64>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Ok(Option::<super::super::MetricNumber>::deserialize(d)?.map(|n| n.as_f64()))
+        }
+    }
+}
+
+impl ParseError {
+    /// A stable identifier for the rule the error breaks, for tools reporting or filtering
+    /// errors.  Identifiers are never reused or renamed, even if a variant is.
+    pub fn rule_id(&self) -> &'static str {
+        match self {
+            Self::BadBucket => "bad-bucket",
+            Self::BadBucketOrder => "bad-bucket-order",
+            Self::BadCounter => "bad-counter",
+            Self::BadCreated => "bad-created",
+            Self::BadExemplar => "bad-exemplar",
+            Self::BadHistogram => "bad-histogram",
+            Self::BadHistogramCount => "bad-histogram-count",
+            Self::BadInfo => "bad-info",
+            Self::BadLabelTooLong => "label-too-long",
+            Self::BadQuantile => "bad-quantile",
+            Self::BadQuantileValue => "bad-quantile-value",
+            Self::BadStateSet => "bad-stateset",
+            Self::BadSummary => "bad-summary",
+            Self::BadSummaryCount => "bad-summary-count",
+            Self::BadSummarySum => "bad-summary-sum",
+            Self::BadSuffix => "bad-suffix",
+            Self::BadUnit => "bad-unit",
+            Self::BadTimestampOutOfOrder => "timestamp-out-of-order",
+            Self::DuplicateMeta => "duplicate-metadata",
+            Self::DuplicateQuantile => "duplicate-quantile",
+            Self::DecreasingBucket => "decreasing-bucket",
+            Self::EmptyLabel => "empty-label",
+            Self::Eof => "eof",
+            Self::Interleave => "interleave",
+            Self::MissingEof => "missing-eof",
+            Self::MissingSample => "missing-sample",
+            Self::NameConflict => "name-conflict",
+            Self::SummaryWithLe => "summary-with-le",
+            Self::UnpairedSummaryCountSum => "unpaired-summary-count-sum",
+            #[cfg(feature = "generic_parse_error")]
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl MetricType {
+    /// The name of this type as it appears in a `# TYPE` descriptor
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::GaugeHistogram => "gaugehistogram",
+            Self::Histogram => "histogram",
+            Self::Info => "info",
+            Self::StateSet => "stateset",
+            Self::Summary => "summary",
+            Self::Unknown => "unknown",
+        }
     }
 }
 
@@ -728,24 +1456,47 @@ impl<'a> TryFrom<lexer::Sample<'a>> for Sample<'a> {
     type Error = ParseError;
 
     fn try_from(l: lexer::Sample<'a>) -> Result<Self> {
-        let number = match l.number {
-            lexer::MetricNumber::Float(f) => f,
-            lexer::MetricNumber::Integer(i) => i as f64,
-        };
+        Self::from_lexer(l, &ParserOptions::default())
+    }
+}
+
+impl<'a> Sample<'a> {
+    fn from_lexer(l: lexer::Sample<'a>, options: &ParserOptions) -> Result<Self> {
+        let mut sample = Self::from_lexer_unclassified(l, options)?;
+        sample.kind = sample.classify()?;
+        Ok(sample)
+    }
 
-        let labels = Label::from_lexer_labels(l.labels.unwrap_or_else(|| vec![]))?;
+    /// Converts a lexed sample without inferring its [`SampleKind`] from its name
+    pub(crate) fn from_lexer_unclassified(
+        l: lexer::Sample<'a>,
+        options: &ParserOptions,
+    ) -> Result<Self> {
+        let labels = Label::from_lexer_labels(l.labels.unwrap_or_default(), options)?;
 
         let exemplar = match l.exemplar {
-            Some(ex) => Some(Exemplar::try_from(ex)?),
+            Some(ex) => Some(Exemplar::from_lexer(ex, options)?),
             None => None,
         };
 
-        let name = l.name;
+        Ok(Self {
+            name: Cow::Borrowed(l.name),
+            labels,
+            number: l.number.into(),
+            timestamp: l.timestamp,
+            exemplar,
+            kind: SampleKind::Other,
+        })
+    }
+
+    /// Infers the [`SampleKind`] from the suffix of the sample's name and its labels
+    pub(crate) fn classify(&self) -> Result<SampleKind> {
+        let (name, labels, number) = (self.name.as_ref(), &self.labels, self.number);
 
         let kind = if name.ends_with("_bucket") {
             // Bucket thresholds MUST NOT equal NaN.
             let threshold_str = labels.get("le").ok_or(ParseError::BadBucket)?;
-            let threshold = match threshold_str.borrow() {
+            let threshold = match threshold_str {
                 "+Inf" => f64::INFINITY,
                 threshold_str => threshold_str
                     .parse::<f64>()
@@ -796,6 +1547,8 @@ impl<'a> TryFrom<lexer::Sample<'a>> for Sample<'a> {
                 Err(ParseError::BadCounter)?
             }
             SampleKind::GSum
+        } else if name.ends_with("_created") {
+            SampleKind::Created
         } else if let Some(quantile) = labels.get("quantile") {
             // Summary quantiles must be float64, as they are estimates and thus fundamentally inaccurate.
             // Quantiles MUST be between 0 and 1 inclusive.
@@ -804,14 +1557,10 @@ impl<'a> TryFrom<lexer::Sample<'a>> for Sample<'a> {
             let quantile = quantile
                 .parse::<f64>()
                 .map_err(|_| ParseError::BadQuantile)
-                .and_then(|q| {
-                    if q.is_nan() {
-                        Err(ParseError::BadQuantile)
-                    } else if q < 0. || q > 1. {
-                        Err(ParseError::BadQuantile)
-                    } else {
-                        Ok(q)
-                    }
+                // NaN isn't within the range either
+                .and_then(|q| match (0. ..=1.).contains(&q) {
+                    true => Ok(q),
+                    false => Err(ParseError::BadQuantile),
                 })?;
 
             SampleKind::Quantile(quantile)
@@ -819,37 +1568,177 @@ impl<'a> TryFrom<lexer::Sample<'a>> for Sample<'a> {
             SampleKind::Other
         };
 
-        Ok(Self {
-            name: l.name,
-            labels,
-            number,
-            timestamp: l.timestamp,
-            exemplar,
-            kind,
+        Ok(kind)
+    }
+}
+
+/// Validates the buckets of a single histogram or gaugehistogram MetricPoint, along with the
+/// sample of the `count` kind that has to match its +Inf bucket.
+fn validate_buckets<'s, 'a>(
+    samples: &[&'s Sample<'a>],
+    count: SampleKind,
+    options: &ParserOptions,
+) -> std::result::Result<(), FamilyError<'s, 'a>> {
+    let buckets = samples
+        .iter()
+        .filter_map(|sample| match sample.kind {
+            SampleKind::HistogramBucket(threshold) => Some((threshold, *sample)),
+            _ => None,
         })
+        .collect_vec();
+
+    // Every MetricPoint has its own +Inf bucket
+    let inf = match buckets
+        .iter()
+        .find(|(threshold, _)| threshold.is_infinite())
+    {
+        Some((_, inf)) => inf.number,
+        None => Err(ParseError::BadHistogram.at(samples[0]))?,
+    };
+
+    // Buckets MUST be sorted in number increasing order of "le"
+    if options.enforce_bucket_order {
+        if let Some(pair) = buckets.windows(2).find(|pair| pair[0].0 >= pair[1].0) {
+            Err(ParseError::BadBucketOrder.at(pair[1].1))?
+        }
     }
+
+    // Buckets are cumulative
+    let decreasing = buckets
+        .iter()
+        .sorted_by(|a, b| a.0.total_cmp(&b.0))
+        .tuple_windows()
+        .find(|(a, b)| {
+            !matches!(
+                a.1.number.partial_cmp(&b.1.number),
+                Some(Ordering::Less | Ordering::Equal)
+            )
+        });
+    if let Some((_, (_, bucket))) = decreasing {
+        Err(ParseError::DecreasingBucket.at(bucket))?
+    }
+
+    if options.validate_histogram_count {
+        let count = samples.iter().find(|sample| sample.kind == count);
+        match count {
+            Some(count) if count.number != inf => Err(ParseError::BadHistogramCount.at(count))?,
+            _ => {}
+        }
+    }
+
+    // Each bucket covers the values less and or equal to it, and the value of the exemplar MUST
+    // be within this range.
+    if options.validate_exemplar_range {
+        let bounds = buckets.iter().sorted_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut lower = f64::NEG_INFINITY;
+        for (upper, bucket) in bounds {
+            if matches!(&bucket.exemplar, Some(exemplar) if !(lower < exemplar.number && exemplar.number <= *upper))
+            {
+                Err(ParseError::BadExemplar.at(bucket))?
+            }
+            lower = *upper;
+        }
+    }
+
+    Ok(())
 }
 
-fn unescape_string<'a>(input: &'a str) -> Cow<'a, str> {
+pub(crate) fn unescape_string<'a>(input: &'a str) -> Cow<'a, str> {
     UNESCAPE_RE.replace_all(input, |caps: &Captures| {
         match caps.get(0).unwrap().as_str() {
-            r"\n" => format!("\n"),
-            r#"\""# => format!(r#"""#),
-            r#"\\"# => format!(r#"\"#),
+            r"\n" => "\n".to_string(),
+            r#"\""# => r#"""#.to_string(),
+            r#"\\"# => r#"\"#.to_string(),
             c => c.to_string(),
         }
     })
 }
 
+/// Builds a [`HashMap`] of [`MetricFamily`] out of the lexed lines.  See [`parse_metric_set`].
+pub fn parse<'a>(
+    lines: Vec<lexer::Line<'a>>,
+) -> std::result::Result<HashMap<&'a str, MetricFamily<'a>>, OmError> {
+    Ok(build(lines, &ParserOptions::default())?
+        .into_iter()
+        .collect())
+}
+
+/// Builds a [`MetricSet`] out of the lexed lines, validating each family along the way.
+pub fn parse_metric_set(lines: Vec<lexer::Line>) -> std::result::Result<MetricSet, OmError> {
+    parse_metric_set_with_options(lines, &ParserOptions::default())
+}
+
+/// Builds a [`MetricSet`] out of the lexed lines, validating each family as per `options`.
 #[tracing::instrument(skip_all)]
-pub fn parse(tokens: Vec<crate::lexer::MetricToken>) -> Result<HashMap<&str, MetricFamily>> {
-    Ok(tokens
+pub fn parse_metric_set_with_options<'a>(
+    lines: Vec<lexer::Line<'a>>,
+    options: &ParserOptions,
+) -> std::result::Result<MetricSet<'a>, OmError> {
+    Ok(parallel::merge(build(lines, options)?))
+}
+
+/// Builds the families out of the lexed lines in document order, each named after the lines it
+/// was parsed from
+fn build<'a>(
+    lines: Vec<lexer::Line<'a>>,
+    options: &ParserOptions,
+) -> std::result::Result<Vec<(&'a str, MetricFamily<'a>)>, OmError> {
+    let mut last_line = (1, "");
+
+    let builder = Builder {
+        options: *options,
+        ..Builder::new()
+    };
+    let builder = lines.into_iter().try_fold(builder, |builder, line| {
+        if !matches!(line.token, lexer::MetricToken::Empty) {
+            last_line = (line.number, line.text);
+        }
+        builder.line(line)
+    })?;
+
+    builder.finalize(last_line)
+}
+
+/// Builds a single family out of its lexed lines, checking it against the families in `known`.
+/// Returns `None` if the lines don't contain any metadata or samples.
+pub(crate) fn parse_family<'a, I>(
+    lines: I,
+    known: &HashSet<String>,
+    options: &ParserOptions,
+) -> std::result::Result<Option<(&'a str, MetricFamily<'a>)>, OmError>
+where
+    I: IntoIterator<Item = lexer::Line<'a>>,
+{
+    build_family(lines, known, options)?.into_family()
+}
+
+/// Feeds a family's lexed lines to a builder, checking each line but leaving the family to be
+/// completed with [`Builder::into_family`].
+fn build_family<'a, 'k, I>(
+    lines: I,
+    known: &'k HashSet<String>,
+    options: &ParserOptions,
+) -> std::result::Result<Builder<'a, 'k>, OmError>
+where
+    I: IntoIterator<Item = lexer::Line<'a>>,
+{
+    let builder = Builder {
+        known: Some(known),
+        options: *options,
+        ..Builder::new()
+    };
+
+    lines
         .into_iter()
-        .try_fold(Builder::new(), |builder, token| match token {
-            lexer::MetricToken::Descriptor(meta) => builder.meta(meta),
-            lexer::MetricToken::Metric(sample) => builder.sample(sample),
-            lexer::MetricToken::Eof => builder.eof(),
-            lexer::MetricToken::Empty => Ok(builder),
-        })?
-        .finalize()?)
+        .try_fold(builder, |builder, line| builder.line(line))
+}
+
+impl<'a> Builder<'a, '_> {
+    /// Completes the single family fed by [`build_family`], `None` if there was nothing to build
+    fn into_family(self) -> std::result::Result<Option<(&'a str, MetricFamily<'a>)>, OmError> {
+        let family = self.name;
+        self.finalize_family(None)
+            .map(|builder| Some((family?, builder.families.into_iter().next()?.1)))
+    }
 }
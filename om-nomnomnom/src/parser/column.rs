@@ -0,0 +1,120 @@
+use super::ParseError;
+
+/// The part of a line a [`ParseError`] is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token {
+    /// The start of the line, for errors about the line as a whole
+    Line,
+    /// The metric name of a sample or descriptor
+    Name,
+    Labels,
+    /// The value of a sample, or what a descriptor says: the type, unit or help text
+    Value,
+    Timestamp,
+    Exemplar,
+}
+
+impl Token {
+    fn of(error: &ParseError) -> Self {
+        match error {
+            ParseError::BadBucket
+            | ParseError::BadBucketOrder
+            | ParseError::BadLabelTooLong
+            | ParseError::BadQuantile
+            | ParseError::EmptyLabel
+            | ParseError::SummaryWithLe => Self::Labels,
+            ParseError::BadCounter
+            | ParseError::BadHistogramCount
+            | ParseError::BadQuantileValue
+            | ParseError::BadSummaryCount
+            | ParseError::BadSummarySum
+            | ParseError::BadUnit
+            | ParseError::DecreasingBucket => Self::Value,
+            ParseError::BadTimestampOutOfOrder => Self::Timestamp,
+            ParseError::BadExemplar => Self::Exemplar,
+            ParseError::Eof | ParseError::MissingEof => Self::Line,
+            _ => Self::Name,
+        }
+    }
+}
+
+/// The 1-based column, in characters, at which the token `error` is about starts in `text`.  A
+/// token the line doesn't have, such as the timestamp of a sample without one, falls back to the
+/// start of the line.
+pub(crate) fn column(text: &str, error: &ParseError) -> usize {
+    let offset = match text.starts_with('#') {
+        true => descriptor(text, Token::of(error)),
+        false => sample(text, Token::of(error)),
+    };
+
+    text[..offset.unwrap_or(0)].chars().count() + 1
+}
+
+/// `# TYPE name type`, `# UNIT name unit` or `# HELP name text`
+fn descriptor(text: &str, token: Token) -> Option<usize> {
+    let word = match token {
+        Token::Name => 2,
+        Token::Value => 3,
+        _ => return None,
+    };
+
+    text.match_indices(' ')
+        .nth(word - 1)
+        .map(|(index, _)| index + 1)
+        .filter(|&index| index < text.len())
+}
+
+/// `name{labels} value timestamp # {labels} value timestamp`
+fn sample(text: &str, token: Token) -> Option<usize> {
+    let name_end = text.find(['{', ' ']).unwrap_or(text.len());
+    let labels_end = match text[name_end..].starts_with('{') {
+        true => name_end + closing_brace(&text[name_end..])?,
+        false => name_end,
+    };
+    let value = skip_spaces(text, labels_end);
+    let value_end = word_end(text, value);
+    let timestamp = Some(skip_spaces(text, value_end)).filter(|&index| {
+        index < text.len() && !text[index..].starts_with('#') && index > value_end
+    });
+    let exemplar = text[value_end..]
+        .find(" # ")
+        .map(|index| value_end + index + 1);
+
+    match token {
+        Token::Line | Token::Name => Some(0),
+        Token::Labels => Some(name_end).filter(|&index| index < labels_end),
+        Token::Value => Some(value).filter(|&index| index < text.len()),
+        Token::Timestamp => timestamp,
+        Token::Exemplar => exemplar,
+    }
+}
+
+/// The offset just past the `}` closing the labels that `text` starts with
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '}' if !quoted => return Some(index + 1),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn skip_spaces(text: &str, from: usize) -> usize {
+    text[from..]
+        .find(|c| c != ' ')
+        .map_or(text.len(), |index| from + index)
+}
+
+fn word_end(text: &str, from: usize) -> usize {
+    text[from..]
+        .find(' ')
+        .map_or(text.len(), |index| from + index)
+}
@@ -0,0 +1,256 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use lazy_static::lazy_static;
+use serde::{
+    de::{Deserialize, Deserializer},
+    ser::{Serialize, SerializeMap, Serializer},
+};
+use smallvec::SmallVec;
+
+use super::{Label, ParseError, Result};
+use crate::serializer::escape_string;
+
+lazy_static! {
+    // Shared by every sample without labels
+    static ref EMPTY: LabelSet<'static> = LabelSet::from_sorted(&mut []);
+}
+
+/// The labels of a [`Sample`](super::Sample) or [`Exemplar`](super::Exemplar).  Along with the
+/// sample's name they identify the series it belongs to.
+///
+/// The labels are sorted by name and can't be changed once the set is built.  Cloning a set is
+/// cheap as the labels are reference counted, and its hash is computed up front so that sets can
+/// be compared and used as keys without going over every label each time.
+#[derive(Clone)]
+pub struct LabelSet<'a> {
+    labels: Arc<[Label<'a>]>,
+    hash: u64,
+}
+
+impl<'a> LabelSet<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a set out of labels, rejecting any name that appears more than once.  Up to four
+    /// labels are sorted inline, the set itself is the only allocation, and no labels at all
+    /// share the empty set instead.
+    pub(crate) fn try_from_labels(mut labels: SmallVec<[Label<'a>; 4]>) -> Result<Self> {
+        if labels.is_empty() {
+            return Ok(Self::default());
+        }
+
+        labels.sort_by(|a, b| a.name.cmp(&b.name));
+        if labels.windows(2).any(|pair| pair[0].name == pair[1].name) {
+            Err(ParseError::DuplicateMeta)?
+        }
+
+        Ok(Self::from_sorted(&mut labels))
+    }
+
+    /// Moves the labels into a new set, leaving empty ones behind
+    fn from_sorted(labels: &mut [Label<'a>]) -> Self {
+        #[cfg(not(feature = "hash_fnv"))]
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+
+        #[cfg(feature = "hash_fnv")]
+        let mut hasher = fnv::FnvHasher::default();
+
+        labels.hash(&mut hasher);
+
+        // Mapping over a slice keeps the iterator's length trusted, so the labels are moved
+        // straight into the `Arc` instead of being collected into a `Vec` and copied
+        let labels = labels
+            .iter_mut()
+            .map(|label| {
+                let empty = Label {
+                    name: Cow::Borrowed(""),
+                    value: Cow::Borrowed(""),
+                };
+                std::mem::replace(label, empty)
+            })
+            .collect();
+
+        Self {
+            labels,
+            hash: hasher.finish(),
+        }
+    }
+
+    /// The value of the label called `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.position(name)
+            .map(|index| self.labels[index].value.as_ref())
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Iterates over the name and value of each label, sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> + '_ {
+        self.labels
+            .iter()
+            .map(|label| (label.name.as_ref(), label.value.as_ref()))
+    }
+
+    /// The labels, sorted by name
+    pub fn labels(&self) -> &[Label<'a>] {
+        &self.labels
+    }
+
+    /// The same set less the label called `name`, if there's one
+    pub fn without(&self, name: &str) -> Self {
+        match self.position(name) {
+            Some(index) => {
+                let mut labels = self.labels.to_vec();
+                labels.remove(index);
+                Self::from_sorted(&mut labels)
+            }
+            None => self.clone(),
+        }
+    }
+
+    /// Copies whatever is still borrowed from the exposition.  See
+    /// [`MetricFamily::into_owned`](super::MetricFamily::into_owned).
+    pub fn into_owned(self) -> LabelSet<'static> {
+        let labels: Vec<_> = self.labels.iter().cloned().map(Label::into_owned).collect();
+
+        LabelSet {
+            labels: labels.into(),
+            hash: self.hash,
+        }
+    }
+
+    pub(super) fn position(&self, name: &str) -> Option<usize> {
+        self.labels
+            .binary_search_by(|label| label.name.as_ref().cmp(name))
+            .ok()
+    }
+}
+
+impl<'a> Default for LabelSet<'a> {
+    fn default() -> Self {
+        EMPTY.clone()
+    }
+}
+
+/// As with a map, a name that appears more than once keeps its last value.
+impl<'a, N, V> FromIterator<(N, V)> for LabelSet<'a>
+where
+    N: Into<Cow<'a, str>>,
+    V: Into<Cow<'a, str>>,
+{
+    fn from_iter<I: IntoIterator<Item = (N, V)>>(iter: I) -> Self {
+        let mut labels: Vec<Label> = iter
+            .into_iter()
+            .map(|(name, value)| Label {
+                name: name.into(),
+                value: value.into(),
+            })
+            .collect();
+
+        // The sort is stable, so the last value of a name is the first one kept
+        labels.reverse();
+        labels.sort_by(|a, b| a.name.cmp(&b.name));
+        labels.dedup_by(|a, b| a.name == b.name);
+
+        Self::from_sorted(&mut labels)
+    }
+}
+
+impl<'a> PartialEq for LabelSet<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash
+            && (Arc::ptr_eq(&self.labels, &other.labels) || self.labels == other.labels)
+    }
+}
+
+impl<'a> Eq for LabelSet<'a> {}
+
+impl<'a> PartialOrd for LabelSet<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Sets are ordered label by label, by name then value
+impl<'a> Ord for LabelSet<'a> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.labels.cmp(&other.labels)
+    }
+}
+
+impl<'a> Hash for LabelSet<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+impl<'a, 'b> std::ops::Index<&'b str> for LabelSet<'a> {
+    type Output = Cow<'a, str>;
+
+    fn index(&self, name: &'b str) -> &Self::Output {
+        let index = self.position(name).expect("no such label");
+        &self.labels[index].value
+    }
+}
+
+impl<'a> fmt::Debug for LabelSet<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Formats the labels as they're written in an exposition, e.g. `{method="GET",code="200"}`.  An
+/// empty set is written as `{}`.
+impl<'a> fmt::Display for LabelSet<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("{")?;
+        for (index, (name, value)) in self.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, r#"{}="{}""#, name, escape_string(value))?;
+        }
+        f.write_str("}")
+    }
+}
+
+/// Serialized as a map of names to values
+impl<'a> Serialize for LabelSet<'a> {
+    fn serialize<S>(&self, s: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = s.serialize_map(Some(self.len()))?;
+        for (name, value) in self.iter() {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de, 'a> Deserialize<'de> for LabelSet<'a> {
+    fn deserialize<D>(d: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let labels = HashMap::<Cow<'a, str>, Cow<'a, str>>::deserialize(d)?;
+        Ok(labels.into_iter().collect())
+    }
+}
@@ -0,0 +1,114 @@
+#[allow(unused)]
+use tracing::{debug, error, info, span, trace, warn, Instrument, Level};
+
+use std::collections::HashSet;
+
+use super::{
+    line_error, missing_eof, parse_family, prometheus, split_families, Group, MetricFamily,
+    MetricSet, ParseError, ParserOptions,
+};
+use crate::{lexer, Format, OmError};
+
+/// Parses lexed OpenMetrics lines, skipping any family that fails validation instead of giving up
+/// on the whole exposition.
+///
+/// Every family that validates is returned in document order, along with a diagnostic for each
+/// one that didn't.  A diagnostic is the error [`parse_metric_set`](super::parse_metric_set) would
+/// have stopped at: its [`ParseError`] names the broken rule and its [`Location`] the line.
+pub fn parse_metric_set_lenient<'a>(
+    lines: Vec<lexer::Line<'a>>,
+    options: &ParserOptions,
+) -> (MetricSet<'a>, Vec<OmError>) {
+    let mut diagnostics = vec![];
+    let last = lines
+        .iter()
+        .rev()
+        .find(|line| !matches!(line.token, lexer::MetricToken::Empty))
+        .map_or((1, ""), |line| (line.number, line.text));
+
+    // Nothing but empty lines may follow the EOF, anything that does is reported once
+    let eof = lines
+        .iter()
+        .position(|line| matches!(line.token, lexer::MetricToken::Eof));
+    let mut lines = lines;
+    let trailing = eof.map(|eof| lines.split_off(eof)).unwrap_or_default();
+
+    match eof {
+        None => diagnostics.push(missing_eof(last)),
+        Some(_) => {
+            if let Some(line) = trailing
+                .iter()
+                .skip(1)
+                .find(|line| !matches!(line.token, lexer::MetricToken::Empty))
+            {
+                diagnostics.push(eof_error(line.number, line.text));
+            }
+        }
+    }
+
+    // Unlike Prometheus, only a descriptor starts an OpenMetrics family
+    let groups = split_families(lines, |family, _, _| family.is_some());
+    let (set, errors) = build(groups, |lines, known| parse_family(lines, known, options));
+    diagnostics.extend(errors);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.location().map(|location| location.line));
+
+    (set, diagnostics)
+}
+
+/// [`parse_metric_set_lenient`] for lexed Prometheus text format lines.
+pub fn parse_prometheus_lenient<'a>(
+    lines: Vec<lexer::Line<'a>>,
+    options: &ParserOptions,
+) -> (MetricSet<'a>, Vec<OmError>) {
+    let groups = split_families(lines, prometheus::is_member);
+    let options = options.format(Format::Prometheus);
+    let (set, mut diagnostics) = build(groups, |lines, known| parse_family(lines, known, &options));
+    diagnostics.sort_by_key(|diagnostic| diagnostic.location().map(|location| location.line));
+
+    (set, diagnostics)
+}
+
+type Family<'a> = Option<(&'a str, MetricFamily<'a>)>;
+
+/// Builds each group of lines into a family of its own.  A group's name is remembered even when
+/// the family is rejected, so a later group for the same name is still reported as interleaved.
+fn build<'a, F>(groups: Vec<Group<'a>>, parse: F) -> (MetricSet<'a>, Vec<OmError>)
+where
+    F: Fn(Vec<lexer::Line<'a>>, &HashSet<String>) -> Result<Family<'a>, OmError>,
+{
+    let mut set = MetricSet::new();
+    let mut known = HashSet::new();
+    let mut diagnostics = vec![];
+
+    for (name, lines) in groups {
+        let texts: Vec<_> = lines.iter().map(|line| (line.number, line.text)).collect();
+        match parse(lines, &known) {
+            Ok(Some((name, family))) => {
+                debug!(name, line = family.line, "family complete");
+                let line = family.line;
+                if set.push(name, family).is_err() {
+                    let text = texts
+                        .iter()
+                        .find(|(number, _)| *number == line)
+                        .map_or("", |(_, text)| text);
+                    diagnostics.push(line_error(ParseError::Interleave, (line, text), Some(name)));
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                debug!(?name, %err, "family skipped");
+                diagnostics.push(err);
+            }
+        }
+
+        if let Some(name) = name {
+            known.insert(name.to_string());
+        }
+    }
+
+    (set, diagnostics)
+}
+
+fn eof_error(number: usize, text: &str) -> OmError {
+    line_error(ParseError::Eof, (number, text), None)
+}
@@ -0,0 +1,158 @@
+use std::{borrow::Cow, collections::HashMap, fmt};
+
+use serde::{
+    de::{self, Deserialize, Deserializer, MapAccess, Visitor},
+    ser::{Serialize, SerializeMap, Serializer},
+};
+
+use super::MetricFamily;
+
+/// A MetricSet is the top level object exposed by OpenMetrics.  Families are kept in the order
+/// they appear in the exposition and can also be looked up by name.
+#[derive(Debug, Default)]
+pub struct MetricSet<'a> {
+    families: Vec<(Cow<'a, str>, MetricFamily<'a>)>,
+    index: HashMap<Cow<'a, str>, usize>,
+}
+
+impl<'a> MetricSet<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a family, returning it back if a family of the same name is already present.
+    pub fn push<N>(&mut self, name: N, family: MetricFamily<'a>) -> Result<(), MetricFamily<'a>>
+    where
+        N: Into<Cow<'a, str>>,
+    {
+        let name = name.into();
+        if self.index.contains_key(&name) {
+            return Err(family);
+        }
+
+        self.index.insert(name.clone(), self.families.len());
+        self.families.push((name, family));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MetricFamily<'a>> {
+        self.index.get(name).map(|i| &self.families[*i].1)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.index.contains_key(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.families.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.families.is_empty()
+    }
+
+    /// Iterates over each family, in document order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &MetricFamily<'a>)> + '_ {
+        self.families
+            .iter()
+            .map(|(name, family)| (name.as_ref(), family))
+    }
+
+    /// Iterates over each family name, in document order
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.families.iter().map(|(name, _)| name.as_ref())
+    }
+
+    /// Iterates over each family, in document order
+    pub fn families(&self) -> impl Iterator<Item = &MetricFamily<'a>> + '_ {
+        self.families.iter().map(|(_, family)| family)
+    }
+
+    /// Copies whatever is still borrowed from the exposition, see [`MetricFamily::into_owned`].
+    pub fn into_owned(self) -> MetricSet<'static> {
+        let families: Vec<_> = self
+            .families
+            .into_iter()
+            .map(|(name, family)| (Cow::<str>::Owned(name.into_owned()), family.into_owned()))
+            .collect();
+        let index = families
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| (name.clone(), i))
+            .collect();
+
+        MetricSet { families, index }
+    }
+}
+
+impl<'a, 'b> std::ops::Index<&'b str> for MetricSet<'a> {
+    type Output = MetricFamily<'a>;
+
+    fn index(&self, name: &'b str) -> &Self::Output {
+        self.get(name).expect("no such MetricFamily")
+    }
+}
+
+impl<'a> IntoIterator for MetricSet<'a> {
+    type Item = (Cow<'a, str>, MetricFamily<'a>);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.families.into_iter()
+    }
+}
+
+impl<'a> From<MetricSet<'a>> for HashMap<Cow<'a, str>, MetricFamily<'a>> {
+    fn from(set: MetricSet<'a>) -> Self {
+        set.into_iter().collect()
+    }
+}
+
+impl<'a> Serialize for MetricSet<'a> {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = s.serialize_map(Some(self.len()))?;
+        for (name, family) in self.iter() {
+            map.serialize_entry(name, family)?;
+        }
+        map.end()
+    }
+}
+
+/// Families are read in the order they're written, and a name may only appear once
+impl<'de, 'a> Deserialize<'de> for MetricSet<'a> {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct SetVisitor;
+
+        impl<'de> Visitor<'de> for SetVisitor {
+            type Value = MetricSet<'static>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map of MetricFamily by name")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut set = MetricSet::new();
+                while let Some((name, family)) = map.next_entry::<String, MetricFamily>()? {
+                    if set.contains_key(&name) {
+                        let message = format!("duplicate MetricFamily «{}»", name);
+                        return Err(de::Error::custom(message));
+                    }
+                    set.push(name, family).expect("the name is new");
+                }
+
+                Ok(set)
+            }
+        }
+
+        d.deserialize_map(SetVisitor)
+    }
+}
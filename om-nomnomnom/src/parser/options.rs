@@ -0,0 +1,156 @@
+use super::{
+    parse_metric_set_lenient, parse_metric_set_with_options, parse_prometheus_lenient,
+    parse_prometheus_with_options, MetricSet,
+};
+use crate::{lexer, Format, OmError};
+
+/// Runtime switches for the more expensive or opinionated validations.
+///
+/// The defaults follow the crate's cargo features, so existing builds behave the same.  Each
+/// setter consumes and returns the options so they can be chained.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParserOptions {
+    pub(crate) format: Format,
+    pub(crate) no_interleave_metric: bool,
+    pub(crate) enforce_timestamp_monotonic: bool,
+    pub(crate) validate_histogram_count: bool,
+    pub(crate) naive_wide_char_support: bool,
+    pub(crate) max_label_length: Option<usize>,
+    pub(crate) enforce_bucket_order: bool,
+    pub(crate) validate_exemplar_range: bool,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        Self {
+            format: Format::OpenMetrics,
+            no_interleave_metric: cfg!(feature = "no_interleave_metric"),
+            enforce_timestamp_monotonic: cfg!(feature = "enforce_timestamp_monotonic"),
+            validate_histogram_count: cfg!(feature = "validate_histogram_count"),
+            naive_wide_char_support: cfg!(feature = "naive_wide_char_support"),
+            max_label_length: Some(128),
+            enforce_bucket_order: true,
+            validate_exemplar_range: false,
+        }
+    }
+}
+
+impl ParserOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The format of the exposition.  Defaults to OpenMetrics.
+    pub fn format(self, format: Format) -> Self {
+        Self { format, ..self }
+    }
+
+    /// Reject samples of a MetricPoint that are interleaved with those of another.
+    pub fn no_interleave_metric(self, no_interleave_metric: bool) -> Self {
+        Self {
+            no_interleave_metric,
+            ..self
+        }
+    }
+
+    /// Require the timestamps of a series to increase monotonically.
+    pub fn enforce_timestamp_monotonic(self, enforce_timestamp_monotonic: bool) -> Self {
+        Self {
+            enforce_timestamp_monotonic,
+            ..self
+        }
+    }
+
+    /// Require a histogram's `_count`, or a gaugehistogram's `_gcount`, to match its `+Inf`
+    /// bucket.
+    pub fn validate_histogram_count(self, validate_histogram_count: bool) -> Self {
+        Self {
+            validate_histogram_count,
+            ..self
+        }
+    }
+
+    /// Measure labels in bytes rather than characters, assuming a worst case of two bytes per
+    /// character (so the limit is doubled).
+    pub fn naive_wide_char_support(self, naive_wide_char_support: bool) -> Self {
+        Self {
+            naive_wide_char_support,
+            ..self
+        }
+    }
+
+    /// The maximum combined length of a label's name and value, `None` for no limit.  Defaults to
+    /// the 128 characters mandated by the spec.
+    pub fn max_label_length(self, max_label_length: Option<usize>) -> Self {
+        Self {
+            max_label_length,
+            ..self
+        }
+    }
+
+    /// Require histogram and gaugehistogram buckets to be sorted by `le`, ending with `+Inf`.
+    pub fn enforce_bucket_order(self, enforce_bucket_order: bool) -> Self {
+        Self {
+            enforce_bucket_order,
+            ..self
+        }
+    }
+
+    /// Require the value of a bucket's exemplar to be within the bucket, that is greater than the
+    /// previous bucket's `le` and no greater than its own.  Off by default, as the OpenMetrics test
+    /// suite accepts exemplars outside their bucket.
+    pub fn validate_exemplar_range(self, validate_exemplar_range: bool) -> Self {
+        Self {
+            validate_exemplar_range,
+            ..self
+        }
+    }
+
+    /// Parses an exposition document with these options.
+    pub fn parse<'a>(&self, data: &'a str) -> Result<MetricSet<'a>, OmError> {
+        let format = match self.format {
+            Format::Auto => Format::detect(data),
+            format => format,
+        };
+
+        match format {
+            Format::Prometheus => {
+                parse_prometheus_with_options(lexer::prometheus::exposition(data)?, self)
+            }
+            _ => parse_metric_set_with_options(lexer::exposition(data)?, self),
+        }
+    }
+
+    /// Parses an exposition document with these options, skipping the lines and families that
+    /// fail instead of stopping at the first error.  See [`parse_metric_set_lenient`].
+    pub fn parse_lenient<'a>(&self, data: &'a str) -> (MetricSet<'a>, Vec<OmError>) {
+        let format = match self.format {
+            Format::Auto => Format::detect(data),
+            format => format,
+        };
+
+        let (mut diagnostics, (set, errors)) = match format {
+            Format::Prometheus => {
+                let (lines, errors) = lexer::prometheus::exposition_lenient(data);
+                (errors, parse_prometheus_lenient(lines, self))
+            }
+            _ => {
+                let (lines, errors) = lexer::exposition_lenient(data);
+                (errors, parse_metric_set_lenient(lines, self))
+            }
+        };
+        diagnostics.extend(errors);
+        diagnostics.sort_by_key(|diagnostic| diagnostic.location().map(|location| location.line));
+
+        (set, diagnostics)
+    }
+
+    /// Whether a label's name and value fit within the length limit
+    pub(crate) fn is_label_length_allowed(&self, name: &str, value: &str) -> bool {
+        match (self.max_label_length, self.naive_wide_char_support) {
+            (None, _) => true,
+            (Some(max), true) => name.len() + value.len() <= max * 2,
+            (Some(max), false) => name.len() + value.chars().count() <= max,
+        }
+    }
+}
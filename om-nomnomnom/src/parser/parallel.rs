@@ -0,0 +1,244 @@
+#[allow(unused)]
+use tracing::{debug, error, info, span, trace, warn, Instrument, Level};
+
+use std::{collections::HashSet, num::NonZeroUsize, panic, thread};
+
+use memchr::memchr;
+
+use super::{
+    build_family, line_error, missing_eof, parse_metric_set_with_options, MetricFamily, MetricSet,
+    ParseError, ParserOptions,
+};
+use crate::{lexer, Format, OmError};
+
+/// Parses an OpenMetrics exposition on up to `threads` threads, `0` meaning as many as there are
+/// cores.  The result, errors included, is the same as [`parse_metric_set_with_options`]'s.
+///
+/// Families are independent once their boundaries are known, so the exposition is first scanned
+/// for the lines that start a family and cut into runs of whole families of about the same size.
+/// Each thread lexes and validates the families of a run on its own, knowing the names of the
+/// families before the run, so that each family is checked against the ones preceding it just
+/// as it would be sequentially: no family may appear twice and no name may clash with another as
+/// per `CONFLICT_SUFFIXES`.  The families are then merged in document order.
+///
+/// Which error gets reported, and on which line, is the same as well.  Each run stops at its
+/// first error and the first one in document order is reported, unless a later line doesn't
+/// lex: the whole exposition is lexed before any of it is parsed, so a lexer error wins.
+///
+/// Only OpenMetrics can be parsed in parallel, asking for the Prometheus format in `options` is
+/// an error.
+#[tracing::instrument(skip_all)]
+pub fn parse_metric_set_parallel<'a>(
+    data: &'a [u8],
+    threads: usize,
+    options: &ParserOptions,
+) -> Result<MetricSet<'a>, OmError> {
+    if options.format == Format::Prometheus {
+        return Err(OmError::UnsupportedFormat(options.format));
+    }
+
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        threads => threads,
+    };
+
+    if threads > 1 {
+        let spans = partition(data);
+        debug!(families = spans.len(), threads, "parsing in parallel");
+        return parse_runs(data, &spans, threads, options);
+    }
+
+    parse_metric_set_with_options(lexer::bytes::exposition(data)?, options)
+}
+
+/// Where a family starts in the exposition, it ends where the next one starts
+#[derive(Debug)]
+struct Span<'a> {
+    /// Offset of the family's first line
+    start: usize,
+    /// 1-based number of the family's first line
+    line: usize,
+    /// The family's name, `None` only if the exposition doesn't name any
+    name: Option<&'a str>,
+}
+
+/// A family parsed on its own
+type Parsed<'a> = Option<(&'a str, MetricFamily<'a>)>;
+
+/// Finds where each family starts.  As with the builder, only a descriptor for another name
+/// starts a new family, so only the lines starting with a hash need a closer look once the first
+/// family is named.
+fn partition(data: &[u8]) -> Vec<Span<'_>> {
+    let mut spans = vec![Span {
+        start: 0,
+        line: 1,
+        name: None,
+    }];
+    let mut current = None;
+    let (mut start, mut line) = (0, 1);
+
+    loop {
+        let end = memchr(b'\n', &data[start..]).map(|end| start + end);
+        let text = &data[start..end.unwrap_or(data.len())];
+
+        if current.is_none() || text.first() == Some(&b'#') {
+            let name = std::str::from_utf8(text).ok().and_then(lexer::line_name);
+            match (name, current) {
+                (Some((name, _)), None) => {
+                    spans[0].name = Some(name);
+                    current = Some(name);
+                }
+                (Some((name, true)), Some(current_name)) if name != current_name => {
+                    spans.push(Span {
+                        start,
+                        line,
+                        name: Some(name),
+                    });
+                    current = Some(name);
+                }
+                _ => {}
+            }
+        }
+
+        match end {
+            Some(end) => {
+                start = end + 1;
+                line += 1;
+            }
+            None => return spans,
+        }
+    }
+}
+
+/// Parses runs of families on separate threads and merges them
+fn parse_runs<'a>(
+    data: &'a [u8],
+    spans: &[Span<'a>],
+    threads: usize,
+    options: &ParserOptions,
+) -> Result<MetricSet<'a>, OmError> {
+    // Each run starts with the first family past its share of the exposition
+    let mut cuts: Vec<usize> = (1..threads)
+        .map(|run| spans.partition_point(|span| span.start < data.len() * run / threads))
+        .collect();
+    cuts.insert(0, 0);
+    cuts.push(spans.len());
+    cuts.dedup();
+
+    let runs: Vec<Result<Vec<Parsed>, OmError>> = thread::scope(|scope| {
+        cuts.windows(2)
+            .map(|cut| {
+                let (before, run) = (&spans[..cut[0]], &spans[cut[0]..cut[1]]);
+                let next = spans.get(cut[1]);
+                scope.spawn(move || parse_run(data, before, run, next, options))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|err| panic::resume_unwind(err))
+            })
+            .collect()
+    });
+
+    let mut families = vec![];
+    let mut error = None;
+    for run in runs {
+        match run {
+            Ok(run) => families.extend(run.into_iter().flatten()),
+            Err(err @ OmError::LexError { .. }) => return Err(err),
+            Err(err) => {
+                error.get_or_insert(err);
+            }
+        }
+    }
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(merge(families)),
+    }
+}
+
+/// Lexes and validates each family of a run, which is followed by `next` if it isn't the last.
+/// Fails with the first lexer error in the run if there's one, and its first error otherwise.
+fn parse_run<'a>(
+    data: &'a [u8],
+    before: &[Span<'a>],
+    run: &[Span<'a>],
+    next: Option<&Span<'a>>,
+    options: &ParserOptions,
+) -> Result<Vec<Parsed<'a>>, OmError> {
+    let mut known: HashSet<String> = before
+        .iter()
+        .filter_map(|span| span.name.map(str::to_string))
+        .collect();
+    let mut parsed = Vec::with_capacity(run.len());
+    let mut error = None;
+
+    for (index, span) in run.iter().enumerate() {
+        let next = run.get(index + 1).or(next);
+        let end = next.map_or(data.len(), |next| next.start);
+        let lines = lexer::bytes::lines_at(&data[span.start..end], span.line, next.is_none())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Past an error the rest of the run is only lexed
+        if error.is_none() {
+            match parse_span(data, lines, next, &known, options) {
+                Ok(family) => parsed.push(family),
+                Err(err) => error = Some(err),
+            }
+            if let Some(name) = span.name {
+                known.insert(name.to_string());
+            }
+        }
+    }
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(parsed),
+    }
+}
+
+/// Validates the lines of a single family, which is followed by `next` if it isn't the last
+fn parse_span<'a>(
+    data: &'a [u8],
+    lines: Vec<lexer::Line<'a>>,
+    next: Option<&Span<'a>>,
+    known: &HashSet<String>,
+    options: &ParserOptions,
+) -> Result<Parsed<'a>, OmError> {
+    let last = lines
+        .iter()
+        .rev()
+        .find(|line| !matches!(line.token, lexer::MetricToken::Empty))
+        .map_or((1, ""), |line| (line.number, line.text));
+    let builder = build_family(lines, known, options)?;
+
+    match (builder.flags.has_eof, next) {
+        // Nothing may follow the EOF, the sequential parser stops at the next family's first line
+        // without completing this one
+        (true, Some(next)) => {
+            let end = memchr(b'\n', &data[next.start..]).map_or(data.len(), |end| next.start + end);
+            let text = lexer::bytes::decode(next.line, &data[next.start..end])?;
+            Err(line_error(ParseError::Eof, (next.line, text), builder.name))
+        }
+        // The exposition must end with an EOF, which is checked before the last family is
+        // completed
+        (false, None) => Err(missing_eof(last)),
+        _ => builder.into_family(),
+    }
+}
+
+/// Puts the families back together in document order
+pub(super) fn merge<'a>(families: Vec<(&'a str, MetricFamily<'a>)>) -> MetricSet<'a> {
+    let mut metric_set = MetricSet::new();
+
+    for (name, family) in families {
+        // Each family was checked against the ones before it, so none is seen twice
+        let pushed = metric_set.push(name, family);
+        debug_assert!(pushed.is_ok(), "«{}» appears twice", name);
+    }
+
+    metric_set
+}
@@ -0,0 +1,103 @@
+#[allow(unused)]
+use tracing::{debug, error, info, span, trace, warn, Instrument, Level};
+
+use std::borrow::Cow;
+
+use super::{
+    parse_metric_set_with_options, MetricSet, MetricType, ParseError, ParserOptions, Result,
+    Sample, SampleKind,
+};
+use crate::{lexer, Format, OmError};
+
+/// Only histograms and summaries reserve suffixes, any other family is free to use them.  A
+/// counter's samples are named after the family, each of them is a total.
+pub(super) fn classify(metric_type: Option<&MetricType>, sample: &Sample) -> Result<SampleKind> {
+    match metric_type {
+        Some(MetricType::Histogram) | Some(MetricType::Summary) => sample.classify(),
+        Some(MetricType::Counter) => {
+            if sample.number.is_nan() || sample.number < 0. {
+                Err(ParseError::BadCounter)?
+            }
+            Ok(SampleKind::Total)
+        }
+        _ => Ok(SampleKind::Other),
+    }
+}
+
+/// The format is much looser than OpenMetrics: there is no `# EOF`, `_total` isn't required on
+/// counters, and a sample without any metadata is simply an untyped family of its own.  The only
+/// rule spanning several samples is that a histogram must have a bucket with {le="+Inf"}.
+pub(super) fn validate_family(metric_type: Option<&MetricType>, samples: &[Sample]) -> Result<()> {
+    if metric_type == Some(&MetricType::Histogram)
+        && !samples.is_empty()
+        && !samples
+            .iter()
+            .any(|sample| sample.kind == SampleKind::HistogramBucket(f64::INFINITY))
+    {
+        Err(ParseError::BadHistogram)?
+    }
+
+    Ok(())
+}
+
+/// Histogram and summary samples carry a suffix on top of the family name
+pub(super) fn is_member(
+    family: Option<&str>,
+    metric_type: Option<&MetricType>,
+    sample_name: &str,
+) -> bool {
+    let family = match family {
+        Some(family) => family,
+        None => return false,
+    };
+
+    let suffixes: &[&str] = match metric_type {
+        Some(MetricType::Histogram) => &["", "_bucket", "_count", "_sum"],
+        Some(MetricType::Summary) => &["", "_count", "_sum"],
+        _ => &[""],
+    };
+
+    sample_name
+        .strip_prefix(family)
+        .map(|suffix| suffixes.contains(&suffix))
+        .unwrap_or(false)
+}
+
+/// Only backslashes and line feeds are escaped in a Prometheus docstring
+pub(super) fn unescape_help(input: &str) -> Cow<'_, str> {
+    if !input.contains('\\') {
+        return Cow::Borrowed(input);
+    }
+
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            (c, _) => out.push(c),
+        }
+    }
+
+    Cow::Owned(out)
+}
+
+/// Builds a [`MetricSet`] out of lines lexed from the Prometheus text exposition format.
+pub fn parse_prometheus(lines: Vec<lexer::Line>) -> std::result::Result<MetricSet, OmError> {
+    parse_prometheus_with_options(lines, &ParserOptions::default())
+}
+
+/// Builds a [`MetricSet`] out of lines lexed from the Prometheus text exposition format.  Only
+/// the label length limits of `options` apply, the other validations are OpenMetrics specific.
+pub fn parse_prometheus_with_options<'a>(
+    lines: Vec<lexer::Line<'a>>,
+    options: &ParserOptions,
+) -> std::result::Result<MetricSet<'a>, OmError> {
+    parse_metric_set_with_options(lines, &options.format(Format::Prometheus))
+}
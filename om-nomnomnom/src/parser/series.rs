@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
+
+use super::{Label, LabelSet, MetricFamily, MetricType, Sample, SampleKind};
+
+/// A MetricPoint: the samples of a family that share a series and a timestamp, e.g. the buckets,
+/// count, sum and created samples of a histogram.
+#[derive(Debug)]
+pub struct MetricPoint<'f, 'a> {
+    /// The labels of the series, see [`MetricFamily::series_labels`]
+    pub labels: LabelSet<'a>,
+    pub timestamp: Option<f64>,
+    /// The samples of the point, in document order
+    pub samples: Vec<&'f Sample<'a>>,
+}
+
+/// The points of a family that share a label set, in document order
+#[derive(Debug)]
+pub struct Series<'f, 'a> {
+    pub labels: LabelSet<'a>,
+    pub points: Vec<MetricPoint<'f, 'a>>,
+}
+
+impl<'a> MetricFamily<'a> {
+    /// The labels identifying the series `sample` belongs to: its own, less the label telling the
+    /// samples of a point apart.  That's `le` for a histogram or gaugehistogram, `quantile` for a
+    /// summary, and the label named after the family for a stateset.
+    ///
+    /// This is the identity the `no_interleave_metric` check uses.
+    pub fn series_labels(&self, sample: &Sample<'a>) -> LabelSet<'a> {
+        match point_label(&self.metric_type, sample) {
+            Some(name) => sample.labels.without(name),
+            None => sample.labels.clone(),
+        }
+    }
+
+    /// Groups the samples into MetricPoints by series and timestamp, in the order each point
+    /// first appears.
+    pub fn points(&self) -> Vec<MetricPoint<'_, 'a>> {
+        group(&self.metric_type, &self.samples)
+            .into_iter()
+            .map(|(key, timestamp, samples)| MetricPoint {
+                labels: key.label_set(),
+                timestamp,
+                samples,
+            })
+            .collect()
+    }
+
+    /// Groups the MetricPoints by series, in the order each series first appears.
+    pub fn series(&self) -> Vec<Series<'_, 'a>> {
+        let mut index: HashMap<LabelSet<'a>, usize> = HashMap::new();
+        let mut series: Vec<Series> = vec![];
+
+        for point in self.points() {
+            match index.get(&point.labels) {
+                Some(i) => series[*i].points.push(point),
+                None => {
+                    index.insert(point.labels.clone(), series.len());
+                    series.push(Series {
+                        labels: point.labels.clone(),
+                        points: vec![point],
+                    });
+                }
+            }
+        }
+
+        series
+    }
+}
+
+impl<'f, 'a> MetricPoint<'f, 'a> {
+    /// The buckets of a histogram or gaugehistogram point and their upper bounds, in document
+    /// order
+    pub fn buckets(&self) -> impl Iterator<Item = (f64, &'f Sample<'a>)> + '_ {
+        self.samples.iter().filter_map(|sample| match sample.kind {
+            SampleKind::HistogramBucket(upper_bound) => Some((upper_bound, *sample)),
+            _ => None,
+        })
+    }
+
+    /// The quantiles of a summary point, in document order
+    pub fn quantiles(&self) -> impl Iterator<Item = (f64, &'f Sample<'a>)> + '_ {
+        self.samples.iter().filter_map(|sample| match sample.kind {
+            SampleKind::Quantile(quantile) => Some((quantile, *sample)),
+            _ => None,
+        })
+    }
+
+    /// The `_count` sample, or `_gcount` for a gaugehistogram
+    pub fn count(&self) -> Option<&'f Sample<'a>> {
+        self.find(|kind| matches!(kind, SampleKind::Count | SampleKind::GCount))
+    }
+
+    /// The `_sum` sample, or `_gsum` for a gaugehistogram
+    pub fn sum(&self) -> Option<&'f Sample<'a>> {
+        self.find(|kind| matches!(kind, SampleKind::Sum | SampleKind::GSum))
+    }
+
+    /// The `_total` sample of a counter
+    pub fn total(&self) -> Option<&'f Sample<'a>> {
+        self.find(|kind| *kind == SampleKind::Total)
+    }
+
+    /// The `_created` sample
+    pub fn created(&self) -> Option<&'f Sample<'a>> {
+        self.find(|kind| *kind == SampleKind::Created)
+    }
+
+    fn find<F>(&self, predicate: F) -> Option<&'f Sample<'a>>
+    where
+        F: Fn(&SampleKind) -> bool,
+    {
+        self.samples
+            .iter()
+            .find(|sample| predicate(&sample.kind))
+            .copied()
+    }
+}
+
+/// The series a sample belongs to, borrowing its labels rather than building a new set for each
+/// sample.  See [`MetricFamily::series_labels`].
+#[derive(Clone, Copy, Debug)]
+pub(super) struct SeriesKey<'f, 'a> {
+    labels: &'f LabelSet<'a>,
+    /// Where the label telling the samples of a point apart is in `labels`
+    skip: Option<usize>,
+}
+
+impl<'f, 'a> SeriesKey<'f, 'a> {
+    pub(super) fn new(metric_type: &MetricType, sample: &'f Sample<'a>) -> Self {
+        Self {
+            labels: &sample.labels,
+            skip: point_label(metric_type, sample).and_then(|name| sample.labels.position(name)),
+        }
+    }
+
+    /// The labels of the series, sorted by name
+    pub(super) fn iter(&self) -> impl Iterator<Item = &'f Label<'a>> + '_ {
+        let skip = self.skip;
+        self.labels
+            .labels()
+            .iter()
+            .enumerate()
+            .filter(move |(index, _)| Some(*index) != skip)
+            .map(|(_, label)| label)
+    }
+
+    fn label_set(&self) -> LabelSet<'a> {
+        match self.skip {
+            Some(index) => self.labels.without(&self.labels.labels()[index].name),
+            None => self.labels.clone(),
+        }
+    }
+}
+
+impl<'f, 'a> PartialEq for SeriesKey<'f, 'a> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.skip, other.skip) {
+            (None, None) => self.labels == other.labels,
+            _ => self.iter().eq(other.iter()),
+        }
+    }
+}
+
+impl<'f, 'a> Eq for SeriesKey<'f, 'a> {}
+
+impl<'f, 'a> Hash for SeriesKey<'f, 'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for label in self.iter() {
+            label.hash(state);
+        }
+    }
+}
+
+/// A point of the family's samples along with its series
+pub(super) type Point<'f, 'a> = (SeriesKey<'f, 'a>, Option<f64>, Vec<&'f Sample<'a>>);
+
+/// Groups samples by series and timestamp, in the order each group first appears.
+pub(super) fn group<'f, 'a>(
+    metric_type: &MetricType,
+    samples: &'f [Sample<'a>],
+) -> Vec<Point<'f, 'a>> {
+    let mut index: HashMap<(SeriesKey, Option<u64>), usize> = HashMap::new();
+    let mut points: Vec<Point> = vec![];
+
+    for sample in samples.iter() {
+        let key = SeriesKey::new(metric_type, sample);
+
+        match index.get(&(key, sample.timestamp.map(f64::to_bits))) {
+            Some(i) => points[*i].2.push(sample),
+            None => {
+                index.insert((key, sample.timestamp.map(f64::to_bits)), points.len());
+                points.push((key, sample.timestamp, vec![sample]));
+            }
+        }
+    }
+
+    points
+}
+
+/// The label telling the samples of a point apart, if the family has one
+fn point_label<'s>(metric_type: &MetricType, sample: &'s Sample) -> Option<&'s str> {
+    match metric_type {
+        MetricType::Histogram | MetricType::GaugeHistogram => Some("le"),
+        MetricType::Summary => Some("quantile"),
+        // The state label shares its name with the MetricFamily, as do the samples
+        MetricType::StateSet => Some(&sample.name),
+        _ => None,
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+
+use super::{series, Exemplar, MetricFamily, MetricNumber, MetricType, Sample, SampleKind};
+
+/// The labels of a view, sorted by name
+pub type LabelMap<'f> = BTreeMap<&'f str, &'f str>;
+
+/// A single point of a «Counter» MetricFamily
+#[derive(Debug)]
+pub struct Counter<'f> {
+    pub labels: LabelMap<'f>,
+    pub timestamp: Option<f64>,
+    pub total: MetricNumber,
+    /// When the point was created, in seconds since the epoch
+    pub created: Option<f64>,
+    pub exemplar: Option<&'f Exemplar<'f>>,
+}
+
+/// A single point of a «Gauge» MetricFamily
+#[derive(Debug)]
+pub struct Gauge<'f> {
+    pub labels: LabelMap<'f>,
+    pub timestamp: Option<f64>,
+    pub value: MetricNumber,
+}
+
+/// A single point of a «Histogram» or «GaugeHistogram» MetricFamily.
+///
+/// For a GaugeHistogram `count` and `sum` hold the `_gcount` and `_gsum` values.
+#[derive(Debug)]
+pub struct Histogram<'f> {
+    pub labels: LabelMap<'f>,
+    pub timestamp: Option<f64>,
+    pub buckets: Vec<Bucket<'f>>,
+    pub count: Option<MetricNumber>,
+    pub sum: Option<MetricNumber>,
+    /// When the point was created, in seconds since the epoch
+    pub created: Option<f64>,
+}
+
+/// A cumulative histogram bucket
+#[derive(Debug)]
+pub struct Bucket<'f> {
+    /// The value of the `le` label
+    pub upper_bound: f64,
+    pub count: MetricNumber,
+    pub exemplar: Option<&'f Exemplar<'f>>,
+}
+
+/// A single point of a «Summary» MetricFamily
+#[derive(Debug)]
+pub struct Summary<'f> {
+    pub labels: LabelMap<'f>,
+    pub timestamp: Option<f64>,
+    /// Sorted by quantile
+    pub quantiles: Vec<Quantile>,
+    pub count: Option<MetricNumber>,
+    pub sum: Option<MetricNumber>,
+    /// When the point was created, in seconds since the epoch
+    pub created: Option<f64>,
+}
+
+/// A summary quantile and its estimated value
+#[derive(Debug)]
+pub struct Quantile {
+    pub quantile: f64,
+    pub value: MetricNumber,
+}
+
+/// A single point of a «StateSet» MetricFamily
+#[derive(Debug)]
+pub struct StateSet<'f> {
+    pub labels: LabelMap<'f>,
+    pub timestamp: Option<f64>,
+    /// Each state, keyed by the value of the label named after the MetricFamily
+    pub states: BTreeMap<&'f str, bool>,
+}
+
+/// A single point of an «Info» MetricFamily
+#[derive(Debug)]
+pub struct Info<'f> {
+    pub labels: LabelMap<'f>,
+    pub timestamp: Option<f64>,
+}
+
+impl<'a> MetricFamily<'a> {
+    /// Returns a view of each point if this is a «Counter» MetricFamily.
+    pub fn as_counters(&self) -> Option<Vec<Counter<'_>>> {
+        if self.metric_type != MetricType::Counter {
+            return None;
+        }
+
+        let counters = group_points(&self.metric_type, &self.samples)
+            .into_iter()
+            .filter_map(|(labels, timestamp, samples)| {
+                let total = samples.iter().find(|s| s.kind == SampleKind::Total)?;
+                Some(Counter {
+                    labels,
+                    timestamp,
+                    total: total.number,
+                    created: created(&samples),
+                    exemplar: total.exemplar.as_ref(),
+                })
+            })
+            .collect();
+
+        Some(counters)
+    }
+
+    /// Returns a view of each point if this is a «Gauge» MetricFamily.
+    pub fn as_gauges(&self) -> Option<Vec<Gauge<'_>>> {
+        if self.metric_type != MetricType::Gauge {
+            return None;
+        }
+
+        let gauges = self
+            .samples
+            .iter()
+            .map(|sample| Gauge {
+                labels: label_map(sample),
+                timestamp: sample.timestamp,
+                value: sample.number,
+            })
+            .collect();
+
+        Some(gauges)
+    }
+
+    /// Returns a view of each point if this is a «Histogram» or «GaugeHistogram» MetricFamily.
+    pub fn as_histograms(&self) -> Option<Vec<Histogram<'_>>> {
+        if self.metric_type != MetricType::Histogram
+            && self.metric_type != MetricType::GaugeHistogram
+        {
+            return None;
+        }
+
+        let histograms = group_points(&self.metric_type, &self.samples)
+            .into_iter()
+            .map(|(labels, timestamp, samples)| {
+                let buckets = samples
+                    .iter()
+                    .filter_map(|sample| match sample.kind {
+                        SampleKind::HistogramBucket(upper_bound) => Some(Bucket {
+                            upper_bound,
+                            count: sample.number,
+                            exemplar: sample.exemplar.as_ref(),
+                        }),
+                        _ => None,
+                    })
+                    .collect();
+
+                Histogram {
+                    labels,
+                    timestamp,
+                    buckets,
+                    count: find_number(&samples, |k| {
+                        *k == SampleKind::Count || *k == SampleKind::GCount
+                    }),
+                    sum: find_number(&samples, |k| {
+                        *k == SampleKind::Sum || *k == SampleKind::GSum
+                    }),
+                    created: created(&samples),
+                }
+            })
+            .collect();
+
+        Some(histograms)
+    }
+
+    /// Returns a view of each point if this is a «Summary» MetricFamily.
+    pub fn as_summaries(&self) -> Option<Vec<Summary<'_>>> {
+        if self.metric_type != MetricType::Summary {
+            return None;
+        }
+
+        let summaries = group_points(&self.metric_type, &self.samples)
+            .into_iter()
+            .map(|(labels, timestamp, samples)| {
+                let mut quantiles: Vec<Quantile> = samples
+                    .iter()
+                    .filter_map(|sample| match sample.kind {
+                        SampleKind::Quantile(quantile) => Some(Quantile {
+                            quantile,
+                            value: sample.number,
+                        }),
+                        _ => None,
+                    })
+                    .collect();
+                quantiles.sort_by(|a, b| a.quantile.total_cmp(&b.quantile));
+
+                Summary {
+                    labels,
+                    timestamp,
+                    quantiles,
+                    count: find_number(&samples, |k| *k == SampleKind::Count),
+                    sum: find_number(&samples, |k| *k == SampleKind::Sum),
+                    created: created(&samples),
+                }
+            })
+            .collect();
+
+        Some(summaries)
+    }
+
+    /// Returns a view of each point if this is a «StateSet» MetricFamily.
+    pub fn as_statesets(&self) -> Option<Vec<StateSet<'_>>> {
+        if self.metric_type != MetricType::StateSet {
+            return None;
+        }
+
+        let statesets = group_points(&self.metric_type, &self.samples)
+            .into_iter()
+            .map(|(labels, timestamp, samples)| StateSet {
+                labels,
+                timestamp,
+                states: samples
+                    .iter()
+                    .filter_map(|sample| {
+                        let state = sample.labels.get(&sample.name)?;
+                        Some((state, sample.number == 1.))
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Some(statesets)
+    }
+
+    /// Returns a view of each point if this is an «Info» MetricFamily.
+    pub fn as_infos(&self) -> Option<Vec<Info<'_>>> {
+        if self.metric_type != MetricType::Info {
+            return None;
+        }
+
+        let infos = self
+            .samples
+            .iter()
+            .map(|sample| Info {
+                labels: label_map(sample),
+                timestamp: sample.timestamp,
+            })
+            .collect();
+
+        Some(infos)
+    }
+}
+
+type Point<'f, 'a> = (LabelMap<'f>, Option<f64>, Vec<&'f Sample<'a>>);
+
+/// Groups samples into points, see [`MetricFamily::points`].
+fn group_points<'f, 'a>(metric_type: &MetricType, samples: &'f [Sample<'a>]) -> Vec<Point<'f, 'a>> {
+    series::group(metric_type, samples)
+        .into_iter()
+        .map(|(key, timestamp, samples)| {
+            let labels = key
+                .iter()
+                .map(|label| (label.name.as_ref(), label.value.as_ref()))
+                .collect();
+            (labels, timestamp, samples)
+        })
+        .collect()
+}
+
+fn label_map<'f>(sample: &'f Sample) -> LabelMap<'f> {
+    sample.labels.iter().collect()
+}
+
+fn find_number<F>(samples: &[&Sample], predicate: F) -> Option<MetricNumber>
+where
+    F: Fn(&SampleKind) -> bool,
+{
+    samples
+        .iter()
+        .find(|sample| predicate(&sample.kind))
+        .map(|sample| sample.number)
+}
+
+fn created(samples: &[&Sample]) -> Option<f64> {
+    samples
+        .iter()
+        .find(|sample| sample.kind == SampleKind::Created)
+        .map(|sample| sample.number.as_f64())
+}
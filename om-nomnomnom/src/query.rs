@@ -0,0 +1,224 @@
+#[allow(unused)]
+use tracing::{debug, error, info, span, trace, warn, Instrument, Level};
+
+use std::str::FromStr;
+
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::space0,
+    combinator::{cut, eof, opt, value},
+    multi::separated_list0,
+    sequence::{delimited, preceded, terminated, tuple},
+    IResult,
+};
+use regex::Regex;
+
+use crate::{
+    lexer::{
+        self,
+        types::{escaped_string1, metric_name1},
+    },
+    parser::{self, MetricSet, Sample},
+};
+
+/// The pseudo label matching a sample's metric name, as in PromQL
+pub const NAME_LABEL: &str = "__name__";
+
+/// Indicates that a selector couldn't be parsed
+#[derive(thiserror::Error, Debug)]
+pub enum SelectorError {
+    #[error("malformed selector at column {column}: {text:?}")]
+    Syntax { column: usize, text: String },
+
+    #[error("malformed regex for «{label}»: {error}")]
+    Regex { label: String, error: regex::Error },
+
+    #[error("a selector needs a metric name or a label matcher")]
+    Empty,
+}
+
+/// How a [`Matcher`] compares a label's value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchOp {
+    /// `=`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `=~`
+    Regex,
+    /// `!~`
+    NotRegex,
+}
+
+/// A single `label op "value"` condition of a [`Selector`].  As in PromQL, a sample without the
+/// label is matched as if its value were empty, and regexes must match the whole value.
+#[derive(Clone, Debug)]
+pub struct Matcher {
+    pub name: String,
+    pub op: MatchOp,
+    pub value: String,
+    regex: Option<Regex>,
+}
+
+impl Matcher {
+    pub fn new(name: &str, op: MatchOp, value: &str) -> Result<Self, SelectorError> {
+        let regex = match op {
+            MatchOp::Regex | MatchOp::NotRegex => {
+                Some(Regex::new(&format!("^(?:{})$", value)).map_err(|error| {
+                    SelectorError::Regex {
+                        label: name.to_string(),
+                        error,
+                    }
+                })?)
+            }
+            MatchOp::Equal | MatchOp::NotEqual => None,
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            op,
+            value: value.to_string(),
+            regex,
+        })
+    }
+
+    /// Whether the value of a label, empty if it's missing, satisfies the matcher
+    pub fn matches(&self, value: &str) -> bool {
+        match self.op {
+            MatchOp::Equal | MatchOp::Regex => self.is_match(value),
+            MatchOp::NotEqual | MatchOp::NotRegex => !self.is_match(value),
+        }
+    }
+
+    /// A sample's metric name is either its own or its family's, so that `http_requests` selects
+    /// all of a counter's samples and `http_requests_total` only the totals.  A negative matcher
+    /// only matches if neither does.
+    fn matches_name(&self, family: &str, sample: &str) -> bool {
+        let any = self.is_match(family) || self.is_match(sample);
+        match self.op {
+            MatchOp::Equal | MatchOp::Regex => any,
+            MatchOp::NotEqual | MatchOp::NotRegex => !any,
+        }
+    }
+
+    /// Whether the value satisfies the matcher, ignoring its negation
+    fn is_match(&self, value: &str) -> bool {
+        match &self.regex {
+            Some(regex) => regex.is_match(value),
+            None => self.value == value,
+        }
+    }
+}
+
+/// A PromQL-style series selector such as `http_requests_total{method=~"GET|POST",code!="500"}`.
+///
+/// The metric name is optional, and is the same as a `__name__="…"` matcher.  It's matched
+/// against both the sample's name and its family's, so `{__name__=~"go_.*"}` picks whole
+/// families.  Other labels are matched against [`Sample::labels`], including `le` and `quantile`.
+/// Values are double-quoted and escaped as in an exposition.
+#[derive(Clone, Debug)]
+pub struct Selector {
+    pub matchers: Vec<Matcher>,
+}
+
+impl Selector {
+    pub fn parse(input: &str) -> Result<Self, SelectorError> {
+        let (name, matchers) = match selector(input) {
+            Ok((_, parsed)) => parsed,
+            Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+                return Err(SelectorError::Syntax {
+                    column: input[..input.len() - e.input.len()].chars().count() + 1,
+                    text: input.to_string(),
+                })
+            }
+            Err(nom::Err::Incomplete(_)) => unreachable!("the parsers are complete"),
+        };
+
+        let matchers = name
+            .map(|name| Matcher::new(NAME_LABEL, MatchOp::Equal, name))
+            .into_iter()
+            .chain(
+                matchers
+                    .into_iter()
+                    .map(|(label, op, value)| Matcher::new(label, op, &value)),
+            )
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match matchers.is_empty() {
+            true => Err(SelectorError::Empty),
+            false => Ok(Self { matchers }),
+        }
+    }
+
+    /// Whether a sample of the named family is selected
+    pub fn matches(&self, family: &str, sample: &Sample) -> bool {
+        self.matchers
+            .iter()
+            .all(|matcher| match matcher.name.as_str() {
+                NAME_LABEL => matcher.matches_name(family, &sample.name),
+                label => matcher.matches(sample.labels.get(label).unwrap_or("")),
+            })
+    }
+
+    /// The selected samples along with the name of their family, in document order
+    pub fn select<'s, 'a>(
+        &'s self,
+        set: &'s MetricSet<'a>,
+    ) -> impl Iterator<Item = (&'s str, &'s Sample<'a>)> + 's {
+        set.iter().flat_map(move |(name, family)| {
+            family
+                .samples
+                .iter()
+                .filter(move |sample| self.matches(name, sample))
+                .map(move |sample| (name, sample))
+        })
+    }
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+type RawMatcher<'a> = (&'a str, MatchOp, String);
+
+/// ```text
+/// [ metric_name ] [ "{" [ label_name op `"` value `"` { "," ... } [ "," ] ] "}" ]
+/// ```
+fn selector(input: &str) -> IResult<&str, (Option<&str>, Vec<RawMatcher<'_>>)> {
+    let (input, _) = space0(input)?;
+    let (input, name) = opt(metric_name1)(input)?;
+    // Past the opening brace, errors are reported where they are rather than at the brace
+    let (input, matchers) = opt(preceded(
+        tuple((space0, tag("{"), space0)),
+        cut(terminated(
+            separated_list0(tuple((space0, tag(","), space0)), matcher),
+            tuple((opt(tuple((space0, tag(",")))), space0, tag("}"))),
+        )),
+    ))(input)?;
+    let (input, _) = tuple((space0, eof))(input)?;
+
+    Ok((input, (name, matchers.unwrap_or_default())))
+}
+
+fn matcher(input: &str) -> IResult<&str, RawMatcher<'_>> {
+    let (input, (label, _, op, _, value)) = tuple((
+        lexer::Label::label_name1,
+        space0,
+        alt((
+            value(MatchOp::Regex, tag("=~")),
+            value(MatchOp::NotRegex, tag("!~")),
+            value(MatchOp::NotEqual, tag("!=")),
+            value(MatchOp::Equal, tag("=")),
+        )),
+        space0,
+        delimited(tag(r#"""#), opt(escaped_string1), tag(r#"""#)),
+    ))(input)?;
+
+    let value = parser::unescape_string(value.unwrap_or("")).into_owned();
+    Ok((input, (label, op, value)))
+}
@@ -0,0 +1,184 @@
+#[allow(unused)]
+use tracing::{debug, error, info, span, trace, warn, Instrument, Level};
+
+use std::{borrow::Cow, collections::HashMap, fmt::Write};
+
+use itertools::Itertools;
+
+use crate::parser::{MetricFamily, MetricSet, MetricType, Sample, SampleKind};
+
+/// Writes the Prometheus text exposition format (version 0.0.4)
+pub mod prometheus;
+
+/// Serializes every [`MetricFamily`] into an exposition document, terminated by `# EOF`.
+///
+/// Families are written in name order so that a family is always written before any family whose
+/// name it is a prefix of.  This keeps the output stable and avoids tripping the name conflict
+/// check when the document is parsed again.
+pub fn write_exposition<W: Write>(
+    w: &mut W,
+    families: &HashMap<&str, MetricFamily>,
+) -> std::fmt::Result {
+    for (name, family) in families.iter().sorted_by_key(|(name, _)| **name) {
+        write_family(w, name, family)?;
+    }
+
+    writeln!(w, "# EOF")
+}
+
+/// Serializes a [`MetricSet`] into an exposition document, keeping the order of its families.
+pub fn write_metric_set<W: Write>(w: &mut W, set: &MetricSet) -> std::fmt::Result {
+    for (name, family) in set.iter() {
+        write_family(w, name, family)?;
+    }
+
+    writeln!(w, "# EOF")
+}
+
+/// Serializes a single [`MetricFamily`].  No `# EOF` marker is written.
+///
+/// A counter parsed from the Prometheus format is named after its samples, often with the
+/// `_total` suffix OpenMetrics reserves for them.  Such a counter is written without the suffix,
+/// and its totals with it, e.g. a counter `x_total` or `x` with samples of the same name both
+/// become a counter `x` with samples `x_total`.
+pub fn write_family<W: Write>(w: &mut W, name: &str, family: &MetricFamily) -> std::fmt::Result {
+    let is_prometheus_counter = family.metric_type == MetricType::Counter
+        && family.samples.iter().any(|sample| {
+            sample.kind == SampleKind::Total && sample.name.strip_prefix(name) != Some("_total")
+        });
+    let name = match is_prometheus_counter {
+        true => name.strip_suffix("_total").unwrap_or(name),
+        false => name,
+    };
+    let total = format!("{}_total", name);
+
+    writeln!(w, "# TYPE {} {}", name, family.metric_type.as_str())?;
+
+    // The space is written even for an empty unit
+    if let Some(unit) = family.unit.as_deref() {
+        writeln!(w, "# UNIT {} {}", name, unit)?;
+    }
+
+    if let Some(help) = family.help.as_ref() {
+        writeln!(w, "# HELP {} {}", name, escape_string(help))?;
+    }
+
+    for sample in family.samples.iter() {
+        match (is_prometheus_counter, &sample.kind) {
+            (true, SampleKind::Total) => write_sample_as(w, &total, sample)?,
+            _ => write_sample(w, sample)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Serializes a single [`Sample`] line, including its exemplar.
+///
+/// The `le` and `quantile` labels are written using the canonical form of the threshold the
+/// parser extracted, not the original text.
+pub fn write_sample<W: Write>(w: &mut W, sample: &Sample) -> std::fmt::Result {
+    write_sample_as(w, &sample.name, sample)
+}
+
+/// [`write_sample`] under another name
+fn write_sample_as<W: Write>(w: &mut W, name: &str, sample: &Sample) -> std::fmt::Result {
+    w.write_str(name)?;
+
+    let labels = sample_labels(sample);
+    if !labels.is_empty() {
+        write_labels(w, labels.into_iter())?;
+    }
+
+    write!(w, " {}", sample.number)?;
+
+    if let Some(timestamp) = sample.timestamp {
+        write!(w, " {}", format_number(timestamp))?;
+    }
+
+    if let Some(exemplar) = sample.exemplar.as_ref() {
+        write!(
+            w,
+            " # {} {}",
+            exemplar.labels,
+            format_number(exemplar.number)
+        )?;
+
+        if let Some(timestamp) = exemplar.timestamp {
+            write!(w, " {}", format_number(timestamp))?;
+        }
+    }
+
+    writeln!(w)
+}
+
+/// Serializes every [`MetricFamily`] into a [`String`].  See [`write_exposition`].
+pub fn to_string(families: &HashMap<&str, MetricFamily>) -> String {
+    let mut out = String::new();
+    write_exposition(&mut out, families).expect("writing to a String can't fail");
+    out
+}
+
+/// The labels of a sample, with the `le` or `quantile` threshold in its canonical form
+fn sample_labels<'s>(sample: &'s Sample) -> Vec<(&'s str, Cow<'s, str>)> {
+    sample
+        .labels
+        .iter()
+        .map(|(name, value)| match (name, &sample.kind) {
+            ("le", SampleKind::HistogramBucket(threshold)) => {
+                ("le", Cow::Owned(format_number(*threshold)))
+            }
+            ("quantile", SampleKind::Quantile(quantile)) => {
+                ("quantile", Cow::Owned(format_number(*quantile)))
+            }
+            (name, _) => (name, Cow::Borrowed(value)),
+        })
+        .collect_vec()
+}
+
+fn write_labels<'a, W, I, V>(w: &mut W, labels: I) -> std::fmt::Result
+where
+    W: Write,
+    I: Iterator<Item = (&'a str, V)>,
+    V: AsRef<str>,
+{
+    write!(
+        w,
+        "{{{}}}",
+        labels
+            .map(|(name, value)| format!(r#"{}="{}""#, name, escape_string(value.as_ref())))
+            .join(",")
+    )
+}
+
+/// Formats a number as per the OpenMetrics rules for canonical numbers
+pub(crate) fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() && n.is_sign_positive() {
+        "+Inf".to_string()
+    } else if n.is_infinite() {
+        "-Inf".to_string()
+    } else {
+        format!("{:?}", n)
+    }
+}
+
+/// The inverse of the parser's unescaping: backslashes, double quotes and line feeds are escaped.
+pub(crate) fn escape_string(input: &str) -> Cow<'_, str> {
+    if !input.contains(['\\', '"', '\n']) {
+        return Cow::Borrowed(input);
+    }
+
+    let mut out = String::with_capacity(input.len() + 2);
+    for c in input.chars() {
+        match c {
+            '\\' => out.push_str(r"\\"),
+            '"' => out.push_str(r#"\""#),
+            '\n' => out.push_str(r"\n"),
+            c => out.push(c),
+        }
+    }
+
+    Cow::Owned(out)
+}
@@ -0,0 +1,119 @@
+use std::{borrow::Cow, fmt::Write};
+
+use super::{sample_labels, write_labels};
+use crate::parser::{MetricFamily, MetricSet, MetricType, Sample, SampleKind};
+
+/// Serializes a [`MetricSet`] into a Prometheus text exposition, keeping the order of its
+/// families.  See [`write_family`].
+pub fn write_metric_set<W: Write>(w: &mut W, set: &MetricSet) -> std::fmt::Result {
+    for (name, family) in set.iter() {
+        write_family(w, name, family)?;
+    }
+
+    Ok(())
+}
+
+/// Serializes a single [`MetricFamily`] into the Prometheus text format.
+///
+/// The format can't express everything OpenMetrics can, so families are translated the way the
+/// reference Python client does it:
+///
+/// - counters and infos are named after their samples, usually with a `_total` or `_info` suffix
+/// - infos and statesets become gauges, gaugehistograms histograms and unknowns untyped
+/// - the `_created`, `_gcount` and `_gsum` samples are moved to gauges of their own, right after
+///   the family
+/// - units and exemplars are dropped, and timestamps are written in milliseconds
+pub fn write_family<W: Write>(w: &mut W, name: &str, family: &MetricFamily) -> std::fmt::Result {
+    let metric_type = match family.metric_type {
+        MetricType::Info | MetricType::StateSet => "gauge",
+        MetricType::GaugeHistogram => "histogram",
+        MetricType::Unknown => "untyped",
+        ref metric_type => metric_type.as_str(),
+    };
+    let help = family.help.as_deref();
+    let (samples, openmetrics): (Vec<&Sample>, Vec<&Sample>) = family
+        .samples
+        .iter()
+        .partition(|sample| !is_openmetrics(sample));
+
+    // Counters and infos are named after their samples, whatever their suffix
+    let family_name = match (&family.metric_type, samples.first()) {
+        (MetricType::Counter | MetricType::Info, Some(sample)) => Cow::Borrowed(&*sample.name),
+        (MetricType::Counter, None) => Cow::Owned(format!("{}_total", name)),
+        (MetricType::Info, None) => Cow::Owned(format!("{}_info", name)),
+        _ => Cow::Borrowed(name),
+    };
+
+    write_descriptors(w, &family_name, metric_type, help)?;
+    for sample in samples {
+        write_sample(w, sample)?;
+    }
+
+    for (kind, suffix) in [
+        (SampleKind::Created, "_created"),
+        (SampleKind::GCount, "_gcount"),
+        (SampleKind::GSum, "_gsum"),
+    ] {
+        let mut samples = openmetrics
+            .iter()
+            .filter(|sample| sample.kind == kind)
+            .peekable();
+
+        if samples.peek().is_some() {
+            write_descriptors(w, &format!("{}{}", name, suffix), "gauge", help)?;
+            for sample in samples {
+                write_sample(w, sample)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Serializes a single [`Sample`] line.  Its exemplar is dropped.
+pub fn write_sample<W: Write>(w: &mut W, sample: &Sample) -> std::fmt::Result {
+    w.write_str(&sample.name)?;
+
+    let labels = sample_labels(sample);
+    if !labels.is_empty() {
+        write_labels(w, labels.into_iter())?;
+    }
+
+    write!(w, " {}", sample.number)?;
+
+    if let Some(timestamp) = sample.timestamp {
+        write!(w, " {}", (timestamp * 1000.).round() as i64)?;
+    }
+
+    writeln!(w)
+}
+
+fn write_descriptors<W: Write>(
+    w: &mut W,
+    name: &str,
+    metric_type: &str,
+    help: Option<&str>,
+) -> std::fmt::Result {
+    if let Some(help) = help {
+        writeln!(w, "# HELP {} {}", name, escape_help(help))?;
+    }
+
+    writeln!(w, "# TYPE {} {}", name, metric_type)
+}
+
+/// Samples that only exist in OpenMetrics
+fn is_openmetrics(sample: &Sample) -> bool {
+    matches!(
+        sample.kind,
+        SampleKind::Created | SampleKind::GCount | SampleKind::GSum
+    )
+}
+
+/// Only backslashes and line feeds are escaped in a Prometheus docstring
+fn escape_help(input: &str) -> Cow<'_, str> {
+    if !input.contains(['\\', '\n']) {
+        return Cow::Borrowed(input);
+    }
+
+    Cow::Owned(input.replace('\\', r"\\").replace('\n', r"\n"))
+}
@@ -0,0 +1,311 @@
+#[allow(unused)]
+use tracing::{debug, error, info, span, trace, warn, Instrument, Level};
+
+use std::{
+    collections::{HashSet, VecDeque},
+    io::{BufRead, ErrorKind, Read},
+};
+
+use memchr::memchr;
+
+use crate::{lexer, lexer::bytes::decode, parser, Format, OmError};
+
+/// How much [`parse_reader`] reads at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Parses an OpenMetrics exposition incrementally.
+///
+/// The exposition is fed in chunks of any size.  Only the lines of the family currently being
+/// read are buffered: once the next family starts the buffered one is validated exactly as
+/// [`parse`](crate::parse) would and handed off to a callback.  The only state kept across
+/// families is the set of names already seen, which is needed to reject interleaved families and
+/// name conflicts.
+#[derive(Debug)]
+pub struct StreamParser {
+    /// Bytes of a line that hasn't been terminated yet
+    partial: Vec<u8>,
+    /// The lines of the family being read, each terminated by a newline
+    buffer: String,
+    /// The line `buffer` starts on
+    start_line: usize,
+    /// The number of lines read so far
+    line: usize,
+    /// The name of the family being read
+    name: Option<String>,
+    /// The names of the families already handed off
+    known: HashSet<String>,
+    options: parser::ParserOptions,
+    has_eof: bool,
+}
+
+impl Default for StreamParser {
+    fn default() -> Self {
+        Self {
+            partial: Vec::new(),
+            buffer: String::new(),
+            start_line: 1,
+            line: 0,
+            name: None,
+            known: HashSet::new(),
+            options: parser::ParserOptions::default(),
+            has_eof: false,
+        }
+    }
+}
+
+impl StreamParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Validates each family as per `options`.  The format is always OpenMetrics, whatever
+    /// `options` says.
+    pub fn with_options(options: parser::ParserOptions) -> Self {
+        Self {
+            options: options.format(Format::OpenMetrics),
+            ..Self::default()
+        }
+    }
+
+    /// Feeds the next chunk of the exposition, calling `on_family` for each family it completes.
+    pub fn feed<F>(&mut self, chunk: &[u8], mut on_family: F) -> Result<(), OmError>
+    where
+        F: FnMut(&str, parser::MetricFamily),
+    {
+        let mut rest = chunk;
+        while let Some(end) = memchr(b'\n', rest) {
+            self.partial.extend_from_slice(&rest[..end]);
+            rest = &rest[end + 1..];
+
+            let mut bytes = std::mem::take(&mut self.partial);
+            self.push_line(&bytes, &mut on_family)?;
+            bytes.clear();
+            self.partial = bytes;
+        }
+
+        self.partial.extend_from_slice(rest);
+
+        Ok(())
+    }
+
+    /// Ends the exposition, calling `on_family` for the last family.
+    pub fn finish<F>(mut self, mut on_family: F) -> Result<(), OmError>
+    where
+        F: FnMut(&str, parser::MetricFamily),
+    {
+        let bytes = std::mem::take(&mut self.partial);
+        self.line += 1;
+        let text = decode(self.line, &bytes)?;
+
+        // The last line is lexed ahead of time so that its errors take precedence, as they would
+        // when lexing the whole exposition at once.
+        lexer::lex_line(self.line, text, true)?;
+
+        if text == "# EOF" {
+            self.has_eof = true;
+        }
+
+        // The line the exposition ends with is still in the buffer
+        if !self.has_eof {
+            let last = match self.buffer.lines().last() {
+                Some(previous) if text.is_empty() => (self.line - 1, previous),
+                _ => (self.line, text),
+            };
+            Err(parser::missing_eof(last))?
+        }
+
+        self.buffer.push_str(text);
+        self.buffer.push('\n');
+        self.flush(true, &mut on_family)
+    }
+
+    fn push_line<F>(&mut self, bytes: &[u8], on_family: &mut F) -> Result<(), OmError>
+    where
+        F: FnMut(&str, parser::MetricFamily),
+    {
+        self.line += 1;
+        let text = decode(self.line, bytes)?;
+
+        // Nothing may follow the EOF, let the builder report whatever did
+        if self.has_eof {
+            self.buffer.push_str(text);
+            self.buffer.push('\n');
+            return self.flush(false, on_family);
+        }
+
+        // Only a descriptor can start a new family, mirroring the builder
+        match (lexer::line_name(text), self.name.as_deref()) {
+            (Some((name, true)), Some(current)) if name != current => {
+                self.flush(false, on_family)?;
+                self.start_line = self.line;
+                self.name = Some(name.to_string());
+            }
+            (Some((name, _)), None) => self.name = Some(name.to_string()),
+            _ => {}
+        }
+
+        if text == "# EOF" {
+            self.has_eof = true;
+        }
+
+        self.buffer.push_str(text);
+        self.buffer.push('\n');
+
+        Ok(())
+    }
+
+    /// Builds a family out of the buffered lines and hands it off
+    fn flush<F>(&mut self, last: bool, on_family: &mut F) -> Result<(), OmError>
+    where
+        F: FnMut(&str, parser::MetricFamily),
+    {
+        let mut buffer = std::mem::take(&mut self.buffer);
+
+        {
+            let texts: Vec<&str> = buffer.split_terminator('\n').collect();
+            let lines = texts
+                .iter()
+                .enumerate()
+                .map(|(index, text)| {
+                    let is_last = last && index + 1 == texts.len();
+                    lexer::lex_line(self.start_line + index, text, is_last)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if let Some((name, family)) = parser::parse_family(lines, &self.known, &self.options)? {
+                let name_owned = name.to_string();
+                debug!(name, line = family.line, "family complete");
+                on_family(name, family);
+                self.known.insert(name_owned);
+            }
+        }
+
+        buffer.clear();
+        self.buffer = buffer;
+
+        Ok(())
+    }
+}
+
+/// Parses an OpenMetrics exposition out of `reader`, calling `on_family` for each family as soon
+/// as it's complete.  See [`StreamParser`].
+pub fn parse_reader<R, F>(reader: R, on_family: F) -> Result<(), OmError>
+where
+    R: Read,
+    F: FnMut(&str, parser::MetricFamily),
+{
+    parse_reader_with_options(reader, &parser::ParserOptions::default(), on_family)
+}
+
+/// [`parse_reader`], validating each family as per `options`.
+pub fn parse_reader_with_options<R, F>(
+    mut reader: R,
+    options: &parser::ParserOptions,
+    mut on_family: F,
+) -> Result<(), OmError>
+where
+    R: Read,
+    F: FnMut(&str, parser::MetricFamily),
+{
+    let mut parser = StreamParser::with_options(*options);
+    let mut chunk = vec![0; CHUNK_SIZE];
+
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => parser.feed(&chunk[..n], &mut on_family)?,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => Err(err)?,
+        }
+    }
+
+    parser.finish(on_family)
+}
+
+/// The families of an OpenMetrics exposition read out of `reader`, owned so they can outlive the
+/// buffer they were read from.  Each is yielded as soon as it's complete, see [`StreamParser`].
+///
+/// The first error ends the iteration.
+pub fn families<R: BufRead>(reader: R) -> Families<R> {
+    families_with_options(reader, &parser::ParserOptions::default())
+}
+
+/// [`families`], validating each family as per `options`.  The format is always OpenMetrics.
+pub fn families_with_options<R: BufRead>(
+    reader: R,
+    options: &parser::ParserOptions,
+) -> Families<R> {
+    Families {
+        reader,
+        parser: Some(StreamParser::with_options(
+            options.format(Format::OpenMetrics),
+        )),
+        complete: VecDeque::new(),
+        error: None,
+    }
+}
+
+/// An iterator over the families of an exposition, see [`families`]
+#[derive(Debug)]
+pub struct Families<R> {
+    reader: R,
+    /// `None` once the exposition is over or an error was found
+    parser: Option<StreamParser>,
+    /// Families completed by the last chunk, not yet yielded
+    complete: VecDeque<(String, parser::MetricFamily<'static>)>,
+    /// The error that ended the exposition, yielded after the families completed before it
+    error: Option<OmError>,
+}
+
+impl<R: BufRead> Families<R> {
+    /// Feeds the next chunk of the reader to the parser, finishing it at the end of the reader
+    fn read(&mut self) -> Result<(), OmError> {
+        let parser = match self.parser.as_mut() {
+            Some(parser) => parser,
+            None => return Ok(()),
+        };
+        let complete = &mut self.complete;
+        let mut on_family = |name: &str, family: parser::MetricFamily| {
+            complete.push_back((name.to_string(), family.into_owned()))
+        };
+
+        let chunk = loop {
+            match self.reader.fill_buf() {
+                Ok(chunk) => break chunk,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => Err(err)?,
+            }
+        };
+
+        match chunk.len() {
+            0 => {
+                let parser = self.parser.take().expect("the parser is still running");
+                parser.finish(on_family)
+            }
+            n => {
+                parser.feed(chunk, &mut on_family)?;
+                self.reader.consume(n);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Families<R> {
+    type Item = Result<(String, parser::MetricFamily<'static>), OmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(family) = self.complete.pop_front() {
+                return Some(Ok(family));
+            }
+            if self.parser.is_none() {
+                return self.error.take().map(Err);
+            }
+            if let Err(err) = self.read() {
+                self.parser = None;
+                self.error = Some(err);
+            }
+        }
+    }
+}
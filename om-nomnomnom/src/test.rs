@@ -1,263 +1,2711 @@
 use crate::*;
-use tracing_test::traced_test;
+use serde_json::{json, Value};
 
+/// The parsed families in the shape of the `parsed` entry of a test.json.  Sample values are
+/// written as they're serialized, so integers stay exact in a snapshot.
+fn parsed_json(families: &parser::MetricSet) -> Value {
+    let families = families
+        .iter()
+        .map(|(name, family)| {
+            let samples = family
+                .samples
+                .iter()
+                .map(|sample| {
+                    let exemplar = sample.exemplar.as_ref().map(|exemplar| {
+                        json!({
+                            "labels": labels_json(exemplar.labels.iter()),
+                            "number": exemplar.number,
+                            "timestamp": exemplar.timestamp,
+                        })
+                    });
+                    json!({
+                        "name": sample.name,
+                        "labels": labels_json(sample.labels.iter()),
+                        "number": sample.number,
+                        "timestamp": sample.timestamp,
+                        "exemplar": exemplar,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let family = json!({
+                "metric_type": family.metric_type,
+                "help": family.help,
+                "unit": family.unit,
+                "samples": samples,
+            });
+            (name.to_string(), family)
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    Value::Object(families)
+}
+
+fn labels_json<I, S>(labels: I) -> Value
+where
+    I: Iterator<Item = (S, S)>,
+    S: AsRef<str>,
+{
+    let mut labels = labels
+        .map(|(name, value)| (name.as_ref().to_string(), value.as_ref().to_string()))
+        .collect::<Vec<_>>();
+    labels.sort();
+
+    labels
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+/// Brings what test.json expects in line with what the lexer and [`parsed_json`] produce.  The
+/// fixtures were written when every value was read as a float and an empty unit as none: numbers
+/// are compared by value, a `null` value is NaN as serde_json writes it, and a `null` unit is an
+/// empty one.
+fn normalize_expected(expected: &Value) -> Value {
+    match expected {
+        Value::Object(map) => {
+            let mut map = map.clone();
+            if map.get("number") == Some(&Value::Null) {
+                map.insert("number".to_string(), json!("NaN"));
+            }
+            if map.get("unit") == Some(&Value::Null) && !map.contains_key("metric_type") {
+                map.insert("unit".to_string(), json!(""));
+            }
+            Value::Object(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), normalize_expected(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => values.iter().map(normalize_expected).collect(),
+        Value::Number(number) => json!(number.as_f64()),
+        value => value.clone(),
+    }
+}
+
+/// [`normalize_expected`] for the `parsed` entry of a test.json, whose labels are sorted as
+/// [`parsed_json`] sorts them and whose samples may leave out a missing exemplar.
+fn normalize_parsed(expected: &Value) -> Value {
+    match expected {
+        Value::Object(map) if map.contains_key("labels") && map.contains_key("number") => {
+            let mut map = map.clone();
+            let labels = map["labels"].as_array().cloned().unwrap_or_default();
+            let labels = labels.iter().map(|label| {
+                (
+                    label["name"].as_str().unwrap(),
+                    label["value"].as_str().unwrap(),
+                )
+            });
+            map.insert("labels".to_string(), labels_json(labels));
+            if map.contains_key("name") {
+                let exemplar = map.get("exemplar").map_or(Value::Null, normalize_parsed);
+                map.insert("exemplar".to_string(), exemplar);
+            }
+            normalize_expected(&Value::Object(map))
+        }
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), normalize_parsed(value)))
+                .collect(),
+        ),
+        Value::Array(values) => values.iter().map(normalize_parsed).collect(),
+        value => normalize_expected(value),
+    }
+}
+
+/// Lists every path at which `actual` differs from `expected`
+fn diff_json(path: &str, expected: &Value, actual: &Value, differences: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for key in expected
+                .keys()
+                .chain(actual.keys().filter(|key| !expected.contains_key(*key)))
+            {
+                let path = format!("{}.{}", path, key);
+                match (expected.get(key), actual.get(key)) {
+                    (Some(expected), Some(actual)) => {
+                        diff_json(&path, expected, actual, differences)
+                    }
+                    (Some(expected), None) => {
+                        differences.push(format!("{}: missing, expected {}", path, expected))
+                    }
+                    (None, Some(actual)) => {
+                        differences.push(format!("{}: unexpected {}", path, actual))
+                    }
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for (index, (expected, actual)) in expected.iter().zip(actual.iter()).enumerate() {
+                diff_json(
+                    &format!("{}[{}]", path, index),
+                    expected,
+                    actual,
+                    differences,
+                );
+            }
+            if expected.len() != actual.len() {
+                differences.push(format!(
+                    "{}: expected {} entries, got {}",
+                    path,
+                    expected.len(),
+                    actual.len()
+                ));
+            }
+        }
+        (expected, actual) if expected != actual => {
+            differences.push(format!("{}: expected {}, got {}", path, expected, actual))
+        }
+        _ => {}
+    }
+}
+
+/// The families as json, with their `le` and `quantile` labels read as numbers: the serializer
+/// writes thresholds in canonical form, so only their value survives a round trip.
+fn thresholds_json(families: &parser::MetricSet) -> Value {
+    let mut value = serde_json::to_value(families).expect("couldn't serialize families");
+    for family in value.as_object_mut().unwrap().values_mut() {
+        for sample in family["samples"].as_array_mut().unwrap() {
+            for label in ["le", "quantile"] {
+                if let Some(threshold) = sample["labels"].get_mut(label) {
+                    let number: f64 = threshold.as_str().unwrap().parse().unwrap();
+                    *threshold = Value::String(number.to_string());
+                }
+            }
+        }
+    }
+
+    value
+}
+
+/// The lexed lines, or the error, as comparable json
+fn lexed_json(result: &Result<Vec<lexer::Line>, OmError>) -> serde_json::Value {
+    match result {
+        Ok(lines) => serde_json::to_value(lines).expect("couldn't serialize tokens"),
+        Err(err) => serde_json::Value::String(err.to_string()),
+    }
+}
+
+/// The serialized families, or the error
+fn parsed_or_error(result: &Result<parser::MetricSet, OmError>) -> String {
+    match result {
+        Ok(metric_set) => serialize_metric_set(metric_set),
+        Err(err) => err.to_string(),
+    }
+}
+
+/// The [`ParseError`](parser::ParseError) an exposition fails with, `None` if it parses.  Any
+/// other error fails the test.
+fn parse_error(data: &str, options: &parser::ParserOptions) -> Option<parser::ParseError> {
+    match parse_with_options(data, options) {
+        Ok(_) => None,
+        Err(OmError::Parse { error, .. }) => Some(error),
+        Err(err) => panic!("unexpected error: {}", err),
+    }
+}
+
+/// The options the cases in parse-tests are checked with.  Each validation whose default
+/// follows a cargo feature is set, so a case passes or fails whatever features are enabled.
+fn conformance_options() -> parser::ParserOptions {
+    parser::ParserOptions::new()
+        .no_interleave_metric(true)
+        .enforce_timestamp_monotonic(true)
+        .validate_histogram_count(true)
+        .naive_wide_char_support(false)
+}
+
+/// A case in parse-tests: its exposition and its test.json
+struct Case {
+    name: &'static str,
+    data: &'static str,
+    meta: Value,
+}
+
+impl Case {
+    fn should_parse(&self) -> bool {
+        self.meta["shouldParse"] == Value::Bool(true)
+    }
+}
+
+/// Compares `actual` with the snapshot called `name`, see `snapshots/README.md`.  With
+/// `UPDATE_SNAPSHOTS` set the snapshot is written instead.
+fn assert_snapshot(name: &str, actual: &Value) {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("snapshots")
+        .join(format!("{}.json", name));
+    let text = serde_json::to_string_pretty(actual).expect("couldn't serialize json") + "\n";
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::write(&path, text).expect("couldn't write snapshot");
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "no snapshot at {}, run with UPDATE_SNAPSHOTS=1 to write it:\n{}",
+            path.display(),
+            text
+        )
+    });
+    let expected: Value = serde_json::from_str(&expected).expect("invalid json");
+    let mut differences = vec![];
+    diff_json(name, &expected, actual, &mut differences);
+    assert!(
+        differences.is_empty(),
+        "differs from its snapshot:\n{}",
+        differences.join("\n")
+    );
+}
+
+/// Generates a test running `$check` on a case in parse-tests
 macro_rules! open_metrics_test {
-    ($test_name:ident$(, $attrib:ident)?) => {
+    ($check:ident, $test_name:ident$(, $attrib:ident)?) => {
         #[test]
         #[traced_test]
         $(#[$attrib])?
         fn $test_name() {
-            let test_data = include_str!(concat!("../../parse-tests/", stringify!($test_name), "/metrics"));
+            let meta = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../parse-tests/", stringify!($test_name), "/test.json"));
+            $check(&crate::test::Case {
+                name: stringify!($test_name),
+                data: include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../parse-tests/", stringify!($test_name), "/metrics")),
+                meta: serde_json::from_str(meta).expect("invalid json"),
+            });
+        }
+    };
+}
 
-            let test_meta = include_str!(concat!("../../parse-tests/", stringify!($test_name), "/test.json"));
-            let test_meta: serde_json::Value = serde_json::from_str(test_meta).expect("invalid json");
-            let should_parse = test_meta["shouldParse"] == serde_json::Value::Bool(true);
+// Defines parse_tests!, which runs open_metrics_test! on every case in parse-tests.  Generated by
+// build.rs.
+include!(concat!(env!("OUT_DIR"), "/parse_tests.rs"));
 
-            let nom_result = lexer::exposition(test_data);
+/// The cases in parse-tests, one module per part of the crate they're run through
+mod conformance {
+    /// The lexer must produce the tokens test.json lists, if it does
+    mod tokens {
+        use crate::test::{diff_json, normalize_expected, Case};
+        use crate::*;
+        use tracing_test::traced_test;
+
+        fn check(case: &Case) {
+            let result = lexer::exposition(case.data);
+            if !case.should_parse() && result.is_err() {
+                return;
+            }
 
-            if !should_parse && nom_result.is_err() {
-                return
+            let lines = result.expect("couldn't lex");
+            let lexed = lines.iter().map(|line| &line.token).collect::<Vec<_>>();
+            let lexed = serde_json::to_value(&lexed).expect("couldn't serialize tokens");
+            if case.meta["tokens"].is_null() {
+                error!(got=%serde_json::to_string_pretty(&lexed).expect("couldn't serialize json"));
+                return;
             }
 
-            let (_, tokens) = nom_result.expect(stringify!($test_name));
+            let mut differences = vec![];
+            diff_json(
+                "tokens",
+                &normalize_expected(&case.meta["tokens"]),
+                &normalize_expected(&lexed),
+                &mut differences,
+            );
+            assert!(
+                differences.is_empty(),
+                "tokens differ from test.json:\n{}",
+                differences.join("\n")
+            );
+        }
+
+        parse_tests!(check);
+    }
 
-            if test_meta["tokens"].is_null() {
-                error!(got=%serde_json::to_string_pretty(&tokens).expect("couldn't serialize json"))
+    /// A case parses if and only if test.json says it should.  The families must match those
+    /// test.json lists, which only a handful of cases do, and their snapshot.  A snapshot is
+    /// om-nomnomnom's own output, so for the other cases this is a regression check only.
+    mod parsed {
+        use crate::test::{
+            assert_snapshot, conformance_options, diff_json, normalize_expected, normalize_parsed,
+            parsed_json, Case,
+        };
+        use crate::*;
+        use tracing_test::traced_test;
+
+        fn check(case: &Case) {
+            let result = conformance_options().parse(case.data);
+            if !case.should_parse() {
+                assert!(result.is_err(), "should not parse");
+                return;
+            }
+
+            let families = result.expect("couldn't parse");
+            info!(parsed=%serde_json::to_string_pretty(&families).expect("couldn't serialize json"));
+            let parsed = parsed_json(&families);
+
+            if !case.meta["parsed"].is_null() {
+                let mut differences = vec![];
+                diff_json(
+                    "parsed",
+                    &normalize_parsed(&case.meta["parsed"]),
+                    &normalize_expected(&parsed),
+                    &mut differences,
+                );
+                assert!(
+                    differences.is_empty(),
+                    "parsed families differ from test.json:\n{}",
+                    differences.join("\n")
+                );
+            }
+
+            assert_snapshot(case.name, &parsed);
+        }
+
+        parse_tests!(check);
+    }
+
+    /// parse → serialize → parse must give back the same families, and settle on the same
+    /// exposition
+    mod roundtrip {
+        use crate::test::{conformance_options, diff_json, thresholds_json, Case};
+        use crate::*;
+        use tracing_test::traced_test;
+
+        fn check(case: &Case) {
+            if !case.should_parse() {
+                return;
+            }
+
+            let options = conformance_options();
+            let families = options.parse(case.data).expect("couldn't parse");
+            let exposition = serialize_metric_set(&families);
+            let reparsed = options
+                .parse(&exposition)
+                .expect("couldn't parse serialized exposition");
+            let mut differences = vec![];
+            diff_json(
+                "reparsed",
+                &thresholds_json(&families),
+                &thresholds_json(&reparsed),
+                &mut differences,
+            );
+            assert!(
+                differences.is_empty(),
+                "families changed through serialization:\n{}",
+                differences.join("\n")
+            );
+            assert_eq!(exposition, serialize_metric_set(&reparsed));
+        }
+
+        parse_tests!(check);
+    }
+
+    /// The streaming parser must agree with the whole document one, however it's chunked
+    mod stream {
+        use crate::test::{conformance_options, Case};
+        use crate::*;
+        use tracing_test::traced_test;
+
+        fn check(case: &Case) {
+            let options = conformance_options();
+            let mut streamed = String::new();
+            let result = crate::stream::parse_reader_with_options(
+                case.data.as_bytes(),
+                &options,
+                |name, family| {
+                    serializer::write_family(&mut streamed, name, &family).unwrap();
+                },
+            );
+            assert_eq!(case.should_parse(), result.is_ok(), "{:?}", result);
+
+            let mut parser = crate::stream::StreamParser::with_options(options);
+            let chunked = case
+                .data
+                .as_bytes()
+                .chunks(7)
+                .try_for_each(|chunk| parser.feed(chunk, |_, _| {}))
+                .and_then(|_| parser.finish(|_, _| {}));
+            assert_eq!(case.should_parse(), chunked.is_ok(), "{:?}", chunked);
+
+            if case.should_parse() {
+                let metric_set = options.parse(case.data).expect("couldn't parse");
+                assert_eq!(serialize_metric_set(&metric_set), streamed + "# EOF\n");
             }
-            // assert_eq!(test_meta["tokens"], serde_json::to_value(&tokens).unwrap());
+        }
+
+        parse_tests!(check);
+    }
 
-            let parser_result = parser::parse(tokens);
+    /// The lenient parser must find a problem exactly when the strict one does
+    mod lenient {
+        use crate::test::{conformance_options, Case};
+        use crate::*;
+        use tracing_test::traced_test;
 
-            if !should_parse {
-                assert!(parser_result.is_err());
-            } else {
-                let metric_set : std::collections::HashMap<&str, parser::MetricFamily> = parser_result.expect("couldn't parse tokens");
-                info!(expected=%serde_json::to_string_pretty(&metric_set).expect("couldn't serialize json"));
-                // assert_eq!(test_meta["parsed"], serde_json::to_value(&metric_set).unwrap());
+        fn check(case: &Case) {
+            let options = conformance_options();
+            let (lenient, diagnostics) = parse_lenient(case.data, &options);
+            assert_eq!(
+                case.should_parse(),
+                diagnostics.is_empty(),
+                "{:?}",
+                diagnostics
+            );
+
+            if case.should_parse() {
+                let metric_set = options.parse(case.data).expect("couldn't parse");
+                assert_eq!(
+                    serialize_metric_set(&metric_set),
+                    serialize_metric_set(&lenient)
+                );
             }
         }
+
+        parse_tests!(check);
+    }
+
+    /// The byte lexer must agree with the nom one, token for token
+    mod bytes {
+        use crate::test::{conformance_options, lexed_json, Case};
+        use crate::*;
+        use tracing_test::traced_test;
+
+        fn check(case: &Case) {
+            let nom = lexer::exposition(case.data);
+            let bytes = lexer::bytes::exposition(case.data.as_bytes());
+            assert_eq!(lexed_json(&nom), lexed_json(&bytes));
+
+            let parsed = bytes.and_then(|lines| {
+                parser::parse_metric_set_with_options(lines, &conformance_options())
+            });
+            assert_eq!(case.should_parse(), parsed.is_ok());
+        }
+
+        parse_tests!(check);
+    }
+
+    /// So must the parallel parser agree with the sequential one, errors included
+    mod parallel {
+        use crate::test::{conformance_options, parsed_or_error, Case};
+        use crate::*;
+        use tracing_test::traced_test;
+
+        fn check(case: &Case) {
+            let options = conformance_options();
+            let parallel = parser::parse_metric_set_parallel(case.data.as_bytes(), 3, &options);
+            assert_eq!(
+                parsed_or_error(&options.parse(case.data)),
+                parsed_or_error(&parallel)
+            );
+        }
+
+        parse_tests!(check);
+    }
+}
+
+mod views {
+    use crate::*;
+    use indoc::indoc;
+
+    #[test]
+    fn histogram_per_labelset() {
+        let families = parse(indoc! {r#"
+            # TYPE a histogram
+            a_bucket{method="GET",le="1.0"} 1
+            a_bucket{method="GET",le="+Inf"} 2
+            a_count{method="GET"} 2
+            a_sum{method="GET"} 3
+            a_created{method="GET"} 1000
+            # EOF
+        "#})
+        .expect("couldn't parse");
+
+        let histograms = families["a"].as_histograms().expect("not a histogram");
+        assert_eq!(1, histograms.len());
+
+        let histogram = &histograms[0];
+        assert_eq!(Some(&"GET"), histogram.labels.get("method"));
+        assert!(!histogram.labels.contains_key("le"));
+        assert_eq!(
+            vec![(1., 1.), (f64::INFINITY, 2.)],
+            histogram
+                .buckets
+                .iter()
+                .map(|b| (b.upper_bound, b.count.as_f64()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(2), histogram.count.and_then(|n| n.as_i64()));
+        assert_eq!(Some(3), histogram.sum.and_then(|n| n.as_i64()));
+        assert_eq!(Some(1000.), histogram.created);
+
+        assert!(families["a"].as_summaries().is_none());
+    }
+
+    #[test]
+    fn summary_quantiles() {
+        let families = parse(indoc! {r#"
+            # TYPE a summary
+            a{quantile="0.5"} 0.7
+            a{quantile="1"} 0.8
+            a_count 1
+            a_sum 2
+            # EOF
+        "#})
+        .expect("couldn't parse");
+
+        let summaries = families["a"].as_summaries().expect("not a summary");
+        assert_eq!(1, summaries.len());
+        assert_eq!(
+            vec![(0.5, 0.7), (1., 0.8)],
+            summaries[0]
+                .quantiles
+                .iter()
+                .map(|q| (q.quantile, q.value.as_f64()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(1), summaries[0].count.and_then(|n| n.as_i64()));
+        assert_eq!(Some(2), summaries[0].sum.and_then(|n| n.as_i64()));
+    }
+
+    #[test]
+    fn stateset_and_info() {
+        let families = parse(indoc! {r#"
+            # TYPE a stateset
+            a{a="bar"} 0
+            a{a="foo"} 1.0
+            # TYPE b info
+            b_info{b="1",foo="bar"} 1
+            # EOF
+        "#})
+        .expect("couldn't parse");
+
+        let statesets = families["a"].as_statesets().expect("not a stateset");
+        assert_eq!(1, statesets.len());
+        assert_eq!(Some(&false), statesets[0].states.get("bar"));
+        assert_eq!(Some(&true), statesets[0].states.get("foo"));
+
+        let infos = families["b"].as_infos().expect("not an info");
+        assert_eq!(Some(&"bar"), infos[0].labels.get("foo"));
+    }
+
+    #[test]
+    fn counter_totals() {
+        let families = parse(indoc! {r#"
+            # TYPE a counter
+            a_total{foo="1"} 1
+            a_created{foo="1"} 12
+            a_total{foo="2"} 2
+            # EOF
+        "#})
+        .expect("couldn't parse");
+
+        let counters = families["a"].as_counters().expect("not a counter");
+        assert_eq!(
+            vec![(1., Some(12.)), (2., None)],
+            counters
+                .iter()
+                .map(|c| (c.total.as_f64(), c.created))
+                .collect::<Vec<_>>()
+        );
     }
 }
 
-open_metrics_test!(bad_blank_line);
-open_metrics_test!(bad_clashing_names_0);
-open_metrics_test!(bad_clashing_names_1);
-open_metrics_test!(bad_clashing_names_2);
-open_metrics_test!(bad_counter_values_0);
-open_metrics_test!(bad_counter_values_1);
-open_metrics_test!(bad_counter_values_2);
-open_metrics_test!(bad_counter_values_3);
-open_metrics_test!(bad_counter_values_4);
-open_metrics_test!(bad_counter_values_5);
-open_metrics_test!(bad_counter_values_6);
-open_metrics_test!(bad_counter_values_7);
-open_metrics_test!(bad_counter_values_8);
-open_metrics_test!(bad_counter_values_9);
-open_metrics_test!(bad_counter_values_10);
-open_metrics_test!(bad_counter_values_11);
-open_metrics_test!(bad_counter_values_12);
-open_metrics_test!(bad_counter_values_13);
-open_metrics_test!(bad_counter_values_14);
-open_metrics_test!(bad_counter_values_15);
-open_metrics_test!(bad_counter_values_16);
-open_metrics_test!(bad_counter_values_17);
-open_metrics_test!(bad_counter_values_18);
-open_metrics_test!(bad_counter_values_19);
-open_metrics_test!(bad_exemplar_complex_chars);
-open_metrics_test!(bad_exemplar_timestamp_0);
-open_metrics_test!(bad_exemplar_timestamp_1);
-open_metrics_test!(bad_exemplar_timestamp_2);
-open_metrics_test!(bad_exemplars_0);
-open_metrics_test!(bad_exemplars_1);
-open_metrics_test!(bad_exemplars_2);
-open_metrics_test!(bad_exemplars_3);
-open_metrics_test!(bad_exemplars_4);
-open_metrics_test!(bad_exemplars_5);
-open_metrics_test!(bad_exemplars_6);
-open_metrics_test!(bad_exemplars_7);
-open_metrics_test!(bad_exemplars_8);
-open_metrics_test!(bad_exemplars_9);
-open_metrics_test!(bad_exemplars_10);
-open_metrics_test!(bad_exemplars_11);
-open_metrics_test!(bad_exemplars_12);
-open_metrics_test!(bad_exemplars_on_unallowed_metric_types_0);
-open_metrics_test!(bad_exemplars_on_unallowed_metric_types_1);
-open_metrics_test!(bad_exemplars_on_unallowed_metric_types_2);
-open_metrics_test!(bad_exemplars_on_unallowed_samples_0);
-open_metrics_test!(bad_exemplars_on_unallowed_samples_1);
-open_metrics_test!(bad_exemplars_on_unallowed_samples_2);
-open_metrics_test!(bad_exemplars_on_unallowed_samples_3);
-open_metrics_test!(bad_grouping_or_ordering_0);
-open_metrics_test!(bad_grouping_or_ordering_1);
-open_metrics_test!(bad_grouping_or_ordering_2);
-open_metrics_test!(bad_grouping_or_ordering_3);
-open_metrics_test!(bad_grouping_or_ordering_4);
-open_metrics_test!(bad_grouping_or_ordering_5);
-open_metrics_test!(bad_grouping_or_ordering_6);
-open_metrics_test!(bad_grouping_or_ordering_7);
-open_metrics_test!(bad_grouping_or_ordering_8);
-open_metrics_test!(bad_grouping_or_ordering_9);
-open_metrics_test!(bad_grouping_or_ordering_10);
-open_metrics_test!(bad_help_0);
-open_metrics_test!(bad_help_1);
-open_metrics_test!(bad_help_2);
-open_metrics_test!(bad_help_3);
-open_metrics_test!(bad_help_4);
-open_metrics_test!(bad_histograms_0);
-
-// If present, the MetricPoint's Created Value Sample MetricName MUST have the suffix
-// "_created". If and only if a Sum Value is present in a MetricPoint, then the MetricPoint's
-// +Inf Bucket value MUST also appear in a Sample with a MetricName with the suffix "_count".
-open_metrics_test!(bad_histograms_1);
-open_metrics_test!(bad_histograms_2);
-
-open_metrics_test!(bad_histograms_3);
-open_metrics_test!(bad_histograms_4);
-open_metrics_test!(bad_histograms_5);
-open_metrics_test!(bad_histograms_6);
-open_metrics_test!(bad_histograms_7);
-open_metrics_test!(bad_histograms_8);
-
-// Buckets MUST be sorted in number increasing order of "le", and the value of the "le" label
-// MUST follow the rules for Canonical Numbers.
-open_metrics_test!(bad_histograms_9);
-
-open_metrics_test!(bad_histograms_10);
-open_metrics_test!(bad_histograms_11);
-open_metrics_test!(bad_histograms_12);
-open_metrics_test!(bad_histograms_13);
-open_metrics_test!(bad_histograms_14);
-open_metrics_test!(bad_info_and_stateset_values_0);
-open_metrics_test!(bad_info_and_stateset_values_1);
-open_metrics_test!(bad_invalid_labels_0);
-open_metrics_test!(bad_invalid_labels_1);
-open_metrics_test!(bad_invalid_labels_2);
-open_metrics_test!(bad_invalid_labels_3);
-open_metrics_test!(bad_invalid_labels_4);
-open_metrics_test!(bad_invalid_labels_5);
-open_metrics_test!(bad_invalid_labels_6);
-open_metrics_test!(bad_invalid_labels_7);
-open_metrics_test!(bad_invalid_labels_8);
-open_metrics_test!(bad_metadata);
-open_metrics_test!(bad_metadata_in_wrong_place_0);
-open_metrics_test!(bad_metadata_in_wrong_place_1);
-open_metrics_test!(bad_metadata_in_wrong_place_2);
-open_metrics_test!(bad_metric_names_0);
-open_metrics_test!(bad_metric_names_1);
-open_metrics_test!(bad_metric_names_2);
-open_metrics_test!(bad_missing_equal_or_label_value_0);
-open_metrics_test!(bad_missing_equal_or_label_value_1);
-open_metrics_test!(bad_missing_equal_or_label_value_2);
-open_metrics_test!(bad_missing_equal_or_label_value_3);
-open_metrics_test!(bad_missing_equal_or_label_value_4);
-open_metrics_test!(bad_missing_or_extra_commas_0);
-open_metrics_test!(bad_missing_or_extra_commas_1);
-open_metrics_test!(bad_missing_or_extra_commas_2);
-
-// A Summary MetricPoint MAY consist of a Count, Sum, Created, and a set of quantiles.
-open_metrics_test!(bad_missing_or_invalid_labels_for_a_type_0);
-
-open_metrics_test!(bad_missing_or_invalid_labels_for_a_type_1);
-open_metrics_test!(bad_missing_or_invalid_labels_for_a_type_2);
-open_metrics_test!(bad_missing_or_invalid_labels_for_a_type_3);
-open_metrics_test!(bad_missing_or_invalid_labels_for_a_type_4);
-open_metrics_test!(bad_missing_or_invalid_labels_for_a_type_5);
-open_metrics_test!(bad_missing_or_invalid_labels_for_a_type_7);
-open_metrics_test!(bad_missing_or_wrong_quotes_on_label_value_0);
-open_metrics_test!(bad_missing_or_wrong_quotes_on_label_value_1);
-open_metrics_test!(bad_missing_or_wrong_quotes_on_label_value_2);
-open_metrics_test!(bad_missing_value_0);
-open_metrics_test!(bad_missing_value_1);
-open_metrics_test!(bad_no_eof);
-open_metrics_test!(bad_repeated_metadata_0);
-open_metrics_test!(bad_repeated_metadata_1);
-open_metrics_test!(bad_repeated_metadata_2);
-open_metrics_test!(bad_repeated_metadata_3);
-open_metrics_test!(bad_stateset_info_values_0);
-open_metrics_test!(bad_stateset_info_values_1);
-open_metrics_test!(bad_stateset_info_values_2);
-open_metrics_test!(bad_stateset_info_values_3);
-open_metrics_test!(bad_text_after_eof_0);
-open_metrics_test!(bad_text_after_eof_1);
-open_metrics_test!(bad_timestamp_1);
-open_metrics_test!(bad_timestamp_2);
-open_metrics_test!(bad_timestamp_3);
-open_metrics_test!(bad_timestamp_4);
-open_metrics_test!(bad_timestamp_5);
-open_metrics_test!(bad_timestamp_6);
-open_metrics_test!(bad_timestamp_7);
-open_metrics_test!(bad_timestamp_8);
-open_metrics_test!(bad_type_0);
-open_metrics_test!(bad_type_1);
-open_metrics_test!(bad_type_2);
-open_metrics_test!(bad_type_3);
-open_metrics_test!(bad_type_4);
-open_metrics_test!(bad_type_5);
-open_metrics_test!(bad_type_6);
-open_metrics_test!(bad_type_7);
-open_metrics_test!(bad_value_0);
-open_metrics_test!(bad_value_1);
-open_metrics_test!(bad_value_2);
-open_metrics_test!(bad_value_3);
-open_metrics_test!(bad_value_4);
-open_metrics_test!(bad_value_5);
-open_metrics_test!(bad_value_6);
-open_metrics_test!(bad_value_7);
-open_metrics_test!(bad_value_8);
-open_metrics_test!(bad_value_9);
-open_metrics_test!(bad_value_10);
-open_metrics_test!(bad_value_11);
-open_metrics_test!(bad_value_12);
-
-open_metrics_test!(counter_exemplars);
-open_metrics_test!(counter_exemplars_empty_brackets);
-open_metrics_test!(counter_unit);
-open_metrics_test!(duplicate_timestamps_0);
-open_metrics_test!(duplicate_timestamps_1);
-open_metrics_test!(empty_brackets);
-open_metrics_test!(empty_help);
-open_metrics_test!(empty_label);
-
-// https://github.com/OpenObservability/OpenMetrics/issues/252
-open_metrics_test!(empty_metadata);
-
-open_metrics_test!(escaping);
-
-// The combined length of the label names and values of an Exemplar's LabelSet MUST NOT exceed 128 UTF-8 character code points.
-// …
-// There is a hard 128 UTF-8 character limit on exemplar length, to prevent misuse of the feature for tracing span data and other event logging.
-// Disable this by setting the feature "naive_wide_char_support" 🙄
-open_metrics_test!(exemplars_wide_chars);
-
-open_metrics_test!(exemplars_with_hash_in_label_values);
-open_metrics_test!(float_gauge);
-open_metrics_test!(gaugehistogram_exemplars);
-open_metrics_test!(hash_in_label_value);
-open_metrics_test!(help_escaping);
-open_metrics_test!(histogram_exemplars);
-open_metrics_test!(histogram_noncanonical);
-open_metrics_test!(info_timestamps);
-open_metrics_test!(label_escaping);
-open_metrics_test!(labels_and_infinite);
-open_metrics_test!(labels_with_curly_braces);
-open_metrics_test!(leading_zeros_float_gauge);
-open_metrics_test!(leading_zeros_simple_gauge);
-open_metrics_test!(nan);
-open_metrics_test!(nan_gauge);
-open_metrics_test!(negative_bucket_gaugehistogram);
-open_metrics_test!(negative_bucket_histogram);
-open_metrics_test!(no_metadata);
-open_metrics_test!(no_newline_after_eof);
-open_metrics_test!(null_byte);
-open_metrics_test!(roundtrip);
-open_metrics_test!(simple_counter);
-open_metrics_test!(simple_gauge);
-open_metrics_test!(simple_gaugehistogram);
-open_metrics_test!(simple_histogram);
-open_metrics_test!(simple_stateset);
-open_metrics_test!(simple_summary);
-open_metrics_test!(summary_quantiles);
-open_metrics_test!(timestamps);
-open_metrics_test!(uint64_counter);
-open_metrics_test!(unit_gauge);
-open_metrics_test!(untyped);
+mod locations {
+    use crate::*;
+    use indoc::indoc;
+
+    #[test]
+    fn lex_error_location() {
+        let err = parse(indoc! {r#"
+            # TYPE a gauge
+            a 1 z
+            # EOF
+        "#})
+        .expect_err("should not lex");
+
+        let location = err.location().expect("no location");
+        assert!(matches!(err, OmError::LexError { .. }));
+        assert_eq!(2, location.line);
+        assert_eq!(4, location.column);
+        assert_eq!("a 1 z", location.text);
+    }
+
+    #[test]
+    fn parse_error_location() {
+        let err = parse(indoc! {r#"
+            # TYPE a counter
+            a_total 1
+            # TYPE b info
+            b_info{b="x"} 2
+            # EOF
+        "#})
+        .expect_err("should not parse");
+
+        match &err {
+            OmError::Parse { error, location } => {
+                assert_eq!(&parser::ParseError::BadInfo, error);
+                assert_eq!(4, location.line);
+                assert_eq!(1, location.column);
+                assert_eq!(r#"b_info{b="x"} 2"#, location.text);
+                assert_eq!(Some("b"), location.family.as_deref());
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+
+        assert_eq!(
+            r#"malformed «Info» MetricFamily at line 4, column 1 in «b»: "b_info{b=\"x\"} 2""#,
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn family_error_location() {
+        let err = parse(indoc! {r#"
+            # TYPE a histogram
+            a_bucket{le="1"} 1
+            a_sum 2
+            # TYPE b gauge
+            b 1
+            # EOF
+        "#})
+        .expect_err("should not parse");
+
+        match &err {
+            OmError::Parse { error, location } => {
+                assert_eq!(&parser::ParseError::BadHistogram, error);
+                assert_eq!(3, location.line);
+                assert_eq!(1, location.column);
+                assert_eq!("a_sum 2", location.text);
+                assert_eq!(Some("a"), location.family.as_deref());
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    /// Errors found once a family is complete are reported at the sample that caused them
+    #[test]
+    fn sample_error_location() {
+        let options = parser::ParserOptions::new()
+            .enforce_timestamp_monotonic(true)
+            .validate_exemplar_range(true);
+        let location = |data: &str| {
+            let err = options.parse(data).expect_err("should not parse");
+            let location = err.location().expect("no location");
+            (err.rule_id(), location.line, location.column)
+        };
+
+        assert_eq!(
+            ("bad-exemplar", 3, 20),
+            location(indoc! {r#"
+                # TYPE a histogram
+                a_bucket{le="1"} 1
+                a_bucket{le="2"} 2 # {id="x"} 5
+                a_bucket{le="+Inf"} 2
+                # EOF
+            "#})
+        );
+        assert_eq!(
+            ("decreasing-bucket", 3, 21),
+            location(indoc! {r#"
+                # TYPE a histogram
+                a_bucket{le="1"} 2
+                a_bucket{le="+Inf"} 1
+                a_count 1
+                a_sum 1
+                # EOF
+            "#})
+        );
+        assert_eq!(
+            ("timestamp-out-of-order", 3, 5),
+            location(indoc! {r#"
+                # TYPE a gauge
+                a 1 2
+                a 1 1
+                # TYPE b gauge
+                b 1
+                # EOF
+            "#})
+        );
+        assert_eq!(
+            ("name-conflict", 3, 8),
+            location(indoc! {r#"
+                # TYPE a_created gauge
+                a_created 1
+                # TYPE a counter
+                a_total 1
+                # EOF
+            "#})
+        );
+    }
+
+    #[test]
+    fn missing_eof_location() {
+        let err = parse(indoc! {r#"
+            # TYPE a gauge
+            a 1
+        "#})
+        .expect_err("should not parse");
+
+        match &err {
+            OmError::Parse { error, location } => {
+                assert_eq!(&parser::ParseError::MissingEof, error);
+                assert_eq!(2, location.line);
+                assert_eq!(4, location.column);
+                assert_eq!("a 1", location.text);
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+}
+
+mod numbers {
+    use crate::parser::MetricNumber;
+    use crate::*;
+    use indoc::indoc;
+
+    #[test]
+    fn integers_are_exact() {
+        let families = parse(indoc! {r#"
+            # TYPE a counter
+            a_total{n="1"} 9223372036854775808
+            a_total{n="2"} 9007199254740993
+            a_total{n="3"} 1.5
+            a_total{n="4"} 1e3
+            # EOF
+        "#})
+        .expect("couldn't parse");
+
+        let numbers = families["a"]
+            .samples
+            .iter()
+            .map(|sample| sample.number)
+            .collect::<Vec<_>>();
+
+        assert!(matches!(
+            numbers[0],
+            MetricNumber::Unsigned(9223372036854775808)
+        ));
+        assert_eq!(Some(9223372036854775808), numbers[0].as_u64());
+        assert_eq!(None, numbers[0].as_i64());
+        assert_eq!(Some(9007199254740993), numbers[1].as_i64());
+        assert!(matches!(numbers[2], MetricNumber::Float(_)));
+        assert_eq!(None, numbers[2].as_i64());
+        assert_eq!(Some(1000), numbers[3].as_i64());
+
+        assert!(numbers[1] > MetricNumber::Integer(9007199254740992));
+        assert_eq!(MetricNumber::Float(1000.), MetricNumber::Integer(1000));
+
+        assert_eq!(
+            "[9223372036854775808,9007199254740993,1.5,1000.0]",
+            serde_json::to_string(&numbers).unwrap()
+        );
+    }
+
+    #[test]
+    fn subtraction() {
+        let (max, below) = (
+            MetricNumber::Unsigned(u64::MAX),
+            MetricNumber::Unsigned(u64::MAX - 1),
+        );
+        assert!(matches!(max - below, MetricNumber::Integer(1)));
+        assert!(matches!(below - max, MetricNumber::Integer(-1)));
+        assert!(matches!(
+            max - MetricNumber::Integer(-1),
+            MetricNumber::Float(n) if n == 2f64.powi(64)
+        ));
+        assert!(matches!(
+            MetricNumber::Integer(9007199254740993) - MetricNumber::Integer(-9007199254740993),
+            MetricNumber::Integer(18014398509481986)
+        ));
+        assert!(matches!(
+            MetricNumber::Integer(3) - MetricNumber::Float(0.5),
+            MetricNumber::Float(n) if n == 2.5
+        ));
+    }
+}
+
+mod metric_set {
+    use crate::*;
+    use indoc::indoc;
+
+    const EXPOSITION: &str = indoc! {r#"
+        # TYPE z gauge
+        z 1
+        # TYPE b counter
+        # HELP b help
+        b_total 1
+        # TYPE a gauge
+        a 1
+        # EOF
+    "#};
+
+    #[test]
+    fn document_order() {
+        let set = parse_metric_set(EXPOSITION).expect("couldn't parse");
+
+        assert_eq!(vec!["z", "b", "a"], set.names().collect::<Vec<_>>());
+        assert_eq!(
+            vec![1, 3, 6],
+            set.families().map(|family| family.line).collect::<Vec<_>>()
+        );
+        assert_eq!(parser::MetricType::Counter, set["b"].metric_type);
+        assert!(set.get("c").is_none());
+
+        assert_eq!(EXPOSITION, serialize_metric_set(&set));
+    }
+
+    #[test]
+    fn duplicate_family() {
+        let err = parse_metric_set(indoc! {r#"
+            # TYPE a gauge
+            a 1
+            # TYPE b gauge
+            b 1
+            # TYPE a gauge
+            a 2
+            # EOF
+        "#})
+        .expect_err("should not parse");
+
+        assert!(matches!(
+            err,
+            OmError::Parse {
+                error: parser::ParseError::Interleave,
+                ..
+            }
+        ));
+    }
+}
+
+mod prometheus {
+    use crate::*;
+    use indoc::indoc;
+
+    const EXPOSITION: &str = indoc! {r#"
+        # A comment that isn't metadata
+        # HELP http_requests The total number of HTTP requests.
+        # TYPE http_requests counter
+        http_requests{method="post",code="200"} 1027 1395066363000
+        http_requests{method="post",code="400"}    3 1395066363000
+
+        # Escaping in label values:
+        msdos_file_access_time_seconds{path="C:\\DIR\\FILE.TXT",error="Cannot find file:\n\"FILE.TXT\""} 1.458255915e9
+
+        # TYPE http_request_duration_seconds histogram
+        http_request_duration_seconds_bucket{le="0.05"} 24054
+        http_request_duration_seconds_bucket{le="+Inf"} 144320
+        http_request_duration_seconds_sum 53423
+        http_request_duration_seconds_count 144320
+
+        # TYPE rpc_duration_seconds summary
+        rpc_duration_seconds{quantile="0.5",} 4773
+        rpc_duration_seconds_sum 1.7560473e+07
+        rpc_duration_seconds_count 2693
+    "#};
+
+    #[test]
+    fn classic_exposition() {
+        let set = parse_prometheus(EXPOSITION).expect("couldn't parse");
+
+        assert_eq!(
+            vec![
+                "http_requests",
+                "msdos_file_access_time_seconds",
+                "http_request_duration_seconds",
+                "rpc_duration_seconds"
+            ],
+            set.names().collect::<Vec<_>>()
+        );
+
+        let requests = &set["http_requests"];
+        assert_eq!(parser::MetricType::Counter, requests.metric_type);
+        assert_eq!(
+            Some("The total number of HTTP requests."),
+            requests.help.as_deref()
+        );
+        assert_eq!(Some(1395066363.), requests.samples[0].timestamp);
+        assert_eq!(1027, requests.samples[0].number.as_i64().unwrap());
+
+        let msdos = &set["msdos_file_access_time_seconds"];
+        assert_eq!(parser::MetricType::Unknown, msdos.metric_type);
+        assert_eq!(r"C:\DIR\FILE.TXT", msdos.samples[0].labels["path"]);
+
+        let histograms = set["http_request_duration_seconds"]
+            .as_histograms()
+            .unwrap();
+        assert_eq!(2, histograms[0].buckets.len());
+        assert_eq!(Some(144320.), histograms[0].count.map(|n| n.as_f64()));
+
+        let summaries = set["rpc_duration_seconds"].as_summaries().unwrap();
+        assert_eq!(0.5, summaries[0].quantiles[0].quantile);
+        assert_eq!(Some(2693.), summaries[0].count.map(|n| n.as_f64()));
+    }
+
+    #[test]
+    fn format_detection() {
+        assert_eq!(Format::Prometheus, Format::detect(EXPOSITION));
+        assert_eq!(Format::OpenMetrics, Format::detect("a 1\n# EOF\n"));
+
+        let set = parse_with_format(EXPOSITION, Format::Auto).expect("couldn't parse");
+        assert_eq!(4, set.len());
+
+        // The same document is not valid OpenMetrics
+        assert!(parse_with_format(EXPOSITION, Format::OpenMetrics).is_err());
+
+        // OpenMetrics that was cut short before its EOF
+        for data in [
+            "# TYPE a counter\na_total 1\n",
+            "# TYPE a counter\na_created 1\n",
+            "# TYPE a gauge\n# UNIT a seconds\n",
+            "# TYPE a info\na_info{a=\"1\"} 1\n",
+            "# TYPE a_total counter\na_total 1 # {trace_id=\"abc\"} 1\n",
+        ] {
+            assert_eq!(Format::OpenMetrics, Format::detect(data), "{}", data);
+            assert!(parse_with_format(data, Format::Auto).is_err(), "{}", data);
+        }
+        assert_eq!(
+            Format::Prometheus,
+            Format::detect("# TYPE a_total counter\na_total 1\n")
+        );
+    }
+
+    #[test]
+    fn counter_values() {
+        for value in ["-1", "NaN"] {
+            let data = format!("# TYPE a counter\na {}\n", value);
+            let err = parse_prometheus(&data).expect_err("should not parse");
+            assert!(
+                matches!(
+                    err,
+                    OmError::Parse {
+                        error: parser::ParseError::BadCounter,
+                        ..
+                    }
+                ),
+                "{}",
+                value
+            );
+        }
+
+        let set = parse_prometheus("# TYPE a counter\na 1\n").expect("couldn't parse");
+        assert_eq!(parser::SampleKind::Total, set["a"].samples[0].kind);
+    }
+
+    #[test]
+    fn histogram_without_inf() {
+        let err = parse_prometheus(indoc! {r#"
+            # TYPE a histogram
+            a_bucket{le="1"} 0
+            a_count 0
+        "#})
+        .expect_err("should not parse");
+
+        assert!(matches!(
+            err,
+            OmError::Parse {
+                error: parser::ParseError::BadHistogram,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn meta_after_samples() {
+        let err = parse_prometheus(indoc! {r#"
+            a 1
+            # TYPE a gauge
+        "#})
+        .expect_err("should not parse");
+
+        assert_eq!(Some(2), err.location().map(|location| location.line));
+    }
+
+    #[test]
+    fn serialize() {
+        let set = parse_metric_set(indoc! {r#"
+            # TYPE a counter
+            # HELP a Help with a \\ and a\nline break
+            a_total{x="1"} 1 1.5 # {trace_id="abc"} 1
+            a_created{x="1"} 12
+            # TYPE g_seconds gaugehistogram
+            # UNIT g_seconds seconds
+            g_seconds_bucket{le="1"} 1
+            g_seconds_bucket{le="+Inf"} 2
+            g_seconds_gcount 2
+            g_seconds_gsum 3
+            # TYPE i info
+            i_info{i="1"} 1
+            # TYPE s stateset
+            s{s="a"} 1
+            # TYPE u unknown
+            u NaN
+            # EOF
+        "#})
+        .expect("couldn't parse");
+
+        let expected = indoc! {r#"
+            # HELP a_total Help with a \\ and a\nline break
+            # TYPE a_total counter
+            a_total{x="1"} 1 1500
+            # HELP a_created Help with a \\ and a\nline break
+            # TYPE a_created gauge
+            a_created{x="1"} 12
+            # TYPE g_seconds histogram
+            g_seconds_bucket{le="1.0"} 1
+            g_seconds_bucket{le="+Inf"} 2
+            # TYPE g_seconds_gcount gauge
+            g_seconds_gcount 2
+            # TYPE g_seconds_gsum gauge
+            g_seconds_gsum 3
+            # TYPE i_info gauge
+            i_info{i="1"} 1
+            # TYPE s gauge
+            s{s="a"} 1
+            # TYPE u untyped
+            u NaN
+        "#};
+        let out = serialize_prometheus(&set);
+        assert_eq!(expected, out);
+
+        let reparsed = parse_prometheus(&out).expect("couldn't parse");
+        assert_eq!(
+            Some("Help with a \\ and a\nline break"),
+            reparsed["a_total"].help.as_deref()
+        );
+        assert_eq!(Some(1.5), reparsed["a_total"].samples[0].timestamp);
+        assert_eq!(reparsed["s"].metric_type, parser::MetricType::Gauge);
+        assert_eq!(2, reparsed["g_seconds"].samples.len());
+    }
+
+    #[test]
+    fn serialize_counters_as_openmetrics() {
+        for (data, expected) in [
+            (
+                "# TYPE x_total counter\nx_total 1\n",
+                "# TYPE x counter\nx_total 1\n# EOF\n",
+            ),
+            (
+                "# TYPE x counter\nx 1\n",
+                "# TYPE x counter\nx_total 1\n# EOF\n",
+            ),
+        ] {
+            let set = parse_prometheus(data).expect("couldn't parse");
+            let out = serialize_metric_set(&set);
+            assert_eq!(expected, out);
+
+            let reparsed = parse_metric_set(&out).expect("couldn't parse");
+            assert_eq!(parser::MetricType::Counter, reparsed["x"].metric_type);
+        }
+
+        // An OpenMetrics counter may have `_total` in its own name
+        let data = "# TYPE x_total counter\nx_total_total 1\n# EOF\n";
+        let set = parse_metric_set(data).expect("couldn't parse");
+        assert_eq!(data, serialize_metric_set(&set));
+    }
+}
+
+mod streaming {
+    use crate::*;
+
+    #[test]
+    fn families_are_handed_off_early() {
+        let mut parser = stream::StreamParser::new();
+        let mut seen = vec![];
+
+        parser
+            .feed(
+                b"# TYPE a gauge\na 1\na{x=\"y\"} 2\n# TYPE b cou",
+                |name, family| seen.push((name.to_string(), family.samples.len(), family.line)),
+            )
+            .expect("couldn't feed");
+        assert!(seen.is_empty());
+
+        parser
+            .feed(b"nter\nb_total 1\n", |name, family| {
+                seen.push((name.to_string(), family.samples.len(), family.line))
+            })
+            .expect("couldn't feed");
+        assert_eq!(vec![("a".to_string(), 2, 1)], seen);
+
+        parser
+            .finish(|name, family| seen.push((name.to_string(), family.samples.len(), family.line)))
+            .expect_err("missing EOF");
+    }
+
+    #[test]
+    fn errors_have_locations() {
+        let err = stream::parse_reader(
+            "# TYPE a gauge\na 1\n# TYPE b counter\nb_total -1\n# TYPE a gauge\na 2\n# EOF\n"
+                .as_bytes(),
+            |_, _| {},
+        )
+        .expect_err("should not parse");
+
+        assert!(matches!(
+            err,
+            OmError::Parse {
+                error: parser::ParseError::BadCounter,
+                ..
+            }
+        ));
+        assert_eq!(Some(4), err.location().map(|location| location.line));
+
+        let err = stream::parse_reader(&b"# TYPE a gauge\na{x=\"\xff\"} 1\n# EOF\n"[..], |_, _| {})
+            .expect_err("should not parse");
+        assert_eq!(
+            Some((2, 6)),
+            err.location()
+                .map(|location| (location.line, location.column))
+        );
+    }
+
+    #[test]
+    fn families_iterator() {
+        let data = "# TYPE a gauge\na 1\na{x=\"y\"} 2\n# TYPE b counter\nb_total 1\n# EOF\n";
+
+        // A tiny buffer splits lines across chunks
+        let reader = std::io::BufReader::with_capacity(3, data.as_bytes());
+        let families = stream::families(reader)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("couldn't parse");
+
+        let seen: Vec<_> = families
+            .iter()
+            .map(|(name, family)| (name.as_str(), family.samples.len(), family.line))
+            .collect();
+        assert_eq!(vec![("a", 2, 1), ("b", 1, 4)], seen);
+    }
+
+    #[test]
+    fn families_iterator_errors() {
+        let data = "# TYPE a gauge\na 1\n# TYPE b counter\nb_total -1\n# TYPE c gauge\nc 1\n";
+        let mut families = stream::families(data.as_bytes());
+
+        // The families completed before the error are yielded first, and nothing after it
+        assert_eq!("a", families.next().unwrap().expect("couldn't parse").0);
+        let err = families.next().unwrap().expect_err("should not parse");
+        assert_eq!(Some(4), err.location().map(|location| location.line));
+        assert!(families.next().is_none());
+
+        let mut families = stream::families("# TYPE a gauge\na 1\n".as_bytes());
+        assert!(matches!(
+            families.next(),
+            Some(Err(OmError::Parse {
+                error: parser::ParseError::MissingEof,
+                ..
+            }))
+        ));
+        assert!(families.next().is_none());
+    }
+
+    #[test]
+    fn always_openmetrics() {
+        // A counter without `_total` is only valid in the Prometheus format
+        let data = "# TYPE a counter\na 1\n# EOF\n";
+
+        for format in [Format::Prometheus, Format::Auto] {
+            let options = parser::ParserOptions::new().format(format);
+            let result = stream::parse_reader_with_options(data.as_bytes(), &options, |_, _| {});
+            assert!(result.is_err(), "{:?}", format);
+
+            let mut families = stream::families_with_options(data.as_bytes(), &options);
+            assert!(families.next().unwrap().is_err(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn empty_exposition() {
+        let mut count = 0;
+        stream::parse_reader("# EOF\n".as_bytes(), |_, _| count += 1).expect("couldn't parse");
+        assert_eq!(0, count);
+        assert_eq!(0, stream::families("# EOF\n".as_bytes()).count());
+        assert!(parse_metric_set("# EOF\n")
+            .expect("couldn't parse")
+            .is_empty());
+    }
+}
+
+mod owned {
+    use crate::*;
+    use indoc::indoc;
+
+    fn assert_send_static<T: Send + 'static>(_: &T) {}
+
+    #[test]
+    fn outlives_the_exposition() {
+        let exposition = indoc! {r#"
+            # TYPE a_seconds histogram
+            # UNIT a_seconds seconds
+            # HELP a_seconds help with \\ and \n
+            a_seconds_bucket{le="1",path="C:\\"} 1 # {trace_id="abc"} 0.5 123
+            a_seconds_bucket{le="+Inf",path="C:\\"} 2
+            a_seconds_count{path="C:\\"} 2
+            a_seconds_sum{path="C:\\"} 3.5
+            # EOF
+        "#}
+        .to_string();
+
+        let families: Vec<(String, parser::MetricFamily<'static>)> = parse_metric_set(&exposition)
+            .expect("couldn't parse")
+            .into_iter()
+            .map(|(name, family)| (name.to_string(), family.into_owned()))
+            .collect();
+        let expected = serialize_metric_set(&parse_metric_set(&exposition).unwrap());
+        drop(exposition);
+
+        assert_send_static(&families);
+        let families = std::thread::spawn(move || families)
+            .join()
+            .expect("thread panicked");
+
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            serializer::write_family(&mut out, name, family).unwrap();
+        }
+        assert_eq!(expected, out + "# EOF\n");
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let set = parse_metric_set(indoc! {r#"
+            # TYPE a histogram
+            a_bucket{le="0.5"} 1 # {trace_id="abc"} 0.25
+            a_bucket{le="+Inf"} 18446744073709551615
+            a_count 18446744073709551615
+            a_sum 3.5
+            # TYPE b gauge
+            b{x="y"} -1.5e-3 1.5
+            b{x="z"} NaN
+            # TYPE c counter
+            c_total{x="y"} 1 # {trace_id="abc"} NaN
+            c_total{x="z"} 2 # {trace_id="def"} -Inf 1.5
+            # EOF
+        "#})
+        .expect("couldn't parse");
+
+        let json = serde_json::to_string(&set["c"]).expect("couldn't serialize");
+        let c: parser::MetricFamily = serde_json::from_str(&json).expect("couldn't deserialize");
+        let exemplars = c
+            .samples
+            .iter()
+            .map(|sample| sample.exemplar.as_ref().unwrap());
+        let numbers: Vec<_> = exemplars.map(|exemplar| exemplar.number).collect();
+        assert!(numbers[0].is_nan());
+        assert_eq!(f64::NEG_INFINITY, numbers[1]);
+
+        for (name, family) in set.iter() {
+            let json = serde_json::to_string(family).expect("couldn't serialize");
+            let family: parser::MetricFamily<'static> =
+                serde_json::from_str(&json).expect("couldn't deserialize");
+
+            let mut expected = String::new();
+            serializer::write_family(&mut expected, name, &set[name]).unwrap();
+            let mut out = String::new();
+            serializer::write_family(&mut out, name, &family).unwrap();
+            assert_eq!(expected, out);
+        }
+    }
+
+    #[test]
+    fn metric_set() {
+        let exposition = indoc! {r#"
+            # TYPE z counter
+            z_total 18446744073709551615
+            # TYPE a gauge
+            a{x="y"} 1.5
+            # EOF
+        "#}
+        .to_string();
+
+        let set = parse_metric_set(&exposition)
+            .expect("couldn't parse")
+            .into_owned();
+        drop(exposition);
+        assert_send_static(&set);
+
+        let json = serde_json::to_string(&set).expect("couldn't serialize");
+        let back: parser::MetricSet<'static> =
+            serde_json::from_str(&json).expect("couldn't deserialize");
+        assert_eq!(vec!["z", "a"], back.names().collect::<Vec<_>>());
+        assert_eq!(serialize_metric_set(&set), serialize_metric_set(&back));
+
+        let err = serde_json::from_str::<parser::MetricSet>(
+            r#"{"a": {"metric_type": "Gauge", "help": null, "unit": null, "samples": []},
+                "a": {"metric_type": "Gauge", "help": null, "unit": null, "samples": []}}"#,
+        )
+        .expect_err("should not deserialize");
+        assert!(
+            err.to_string().contains("duplicate MetricFamily «a»"),
+            "{}",
+            err
+        );
+    }
+}
+
+mod options {
+    use super::parse_error;
+    use crate::*;
+    use indoc::indoc;
+    use parser::{ParseError, ParserOptions};
+
+    #[test]
+    fn interleave() {
+        let data = indoc! {r#"
+            # TYPE a gauge
+            a{x="1"} 1
+            a{x="2"} 1
+            a{x="1"} 2
+            # EOF
+        "#};
+
+        let strict = ParserOptions::new().no_interleave_metric(true);
+        assert!(matches!(
+            parse_error(data, &strict),
+            Some(ParseError::Interleave)
+        ));
+        assert!(parse_error(data, &strict.no_interleave_metric(false)).is_none());
+    }
+
+    #[test]
+    fn interleave_points() {
+        // The buckets of a point tell each other apart by their `le` label alone
+        let data = indoc! {r#"
+            # TYPE a histogram
+            a_bucket{x="1",le="+Inf"} 1
+            a_bucket{x="2",le="+Inf"} 1
+            a_bucket{x="1",le="1"} 0
+            # EOF
+        "#};
+
+        let strict = ParserOptions::new().no_interleave_metric(true);
+        assert!(matches!(
+            parse_error(data, &strict),
+            Some(ParseError::Interleave)
+        ));
+
+        let data = indoc! {r#"
+            # TYPE a stateset
+            a{a="x"} 1
+            a{a="y"} 0
+            a{a="z"} 0
+            # EOF
+        "#};
+        assert!(parse_error(data, &strict).is_none());
+    }
+
+    #[test]
+    fn timestamp_monotonic() {
+        let data = indoc! {r#"
+            # TYPE a gauge
+            a 1 2
+            a 1 1
+            # EOF
+        "#};
+
+        let options = ParserOptions::new()
+            .no_interleave_metric(false)
+            .enforce_timestamp_monotonic(true);
+        assert!(matches!(
+            parse_error(data, &options),
+            Some(ParseError::BadTimestampOutOfOrder)
+        ));
+        assert!(parse_error(data, &options.enforce_timestamp_monotonic(false)).is_none());
+    }
+
+    #[test]
+    fn histogram_count() {
+        let data = indoc! {r#"
+            # TYPE a histogram
+            a_bucket{le="+Inf"} 2
+            a_count 3
+            a_sum 1
+            # EOF
+        "#};
+
+        let options = ParserOptions::new().validate_histogram_count(true);
+        assert!(matches!(
+            parse_error(data, &options),
+            Some(ParseError::BadHistogramCount)
+        ));
+        assert!(parse_error(data, &options.validate_histogram_count(false)).is_none());
+    }
+
+    #[test]
+    fn bucket_order() {
+        let data = indoc! {r#"
+            # TYPE a histogram
+            a_bucket{le="2"} 0
+            a_bucket{le="1"} 0
+            a_bucket{le="+Inf"} 0
+            # EOF
+        "#};
+
+        let options = ParserOptions::new();
+        assert!(matches!(
+            parse_error(data, &options),
+            Some(ParseError::BadBucketOrder)
+        ));
+        assert!(parse_error(data, &options.enforce_bucket_order(false)).is_none());
+    }
+
+    #[test]
+    fn exemplar_range() {
+        let strict = ParserOptions::new().validate_exemplar_range(true);
+        let bucket = |exemplar: &str| {
+            format!(
+                "# TYPE a gaugehistogram\n\
+                 a_bucket{{le=\"1\"}} 0\n\
+                 a_bucket{{le=\"2\"}} 1 # {{}} {}\n\
+                 a_bucket{{le=\"+Inf\"}} 1\n\
+                 # EOF\n",
+                exemplar
+            )
+        };
+
+        for exemplar in ["1.5", "2"] {
+            assert!(parse_error(&bucket(exemplar), &strict).is_none());
+        }
+        for exemplar in ["0.5", "1", "3", "NaN"] {
+            let data = bucket(exemplar);
+            assert!(matches!(
+                parse_error(&data, &strict),
+                Some(ParseError::BadExemplar)
+            ));
+            assert!(parse_error(&data, &ParserOptions::new()).is_none());
+        }
+
+        // The first bucket has no lower bound, the last no upper one
+        let data = indoc! {r#"
+            # TYPE a histogram
+            a_bucket{le="0"} 1 # {} -100
+            a_bucket{le="+Inf"} 1 # {} 1e300
+            a_count 1
+            a_sum 0
+            # EOF
+        "#};
+        assert!(parse_error(data, &strict).is_none());
+    }
+
+    #[test]
+    fn label_length() {
+        let data = "# TYPE a gauge\na{name=\"ééé\"} 1\n# EOF\n";
+
+        let options = ParserOptions::new()
+            .naive_wide_char_support(false)
+            .max_label_length(Some(7));
+        assert!(parse_error(data, &options).is_none());
+        assert!(matches!(
+            parse_error(data, &options.max_label_length(Some(6))),
+            Some(ParseError::BadLabelTooLong)
+        ));
+
+        // 4 + 6 bytes fit within twice the limit
+        let naive = options.naive_wide_char_support(true);
+        assert!(parse_error(data, &naive.max_label_length(Some(5))).is_none());
+        assert!(matches!(
+            parse_error(data, &naive.max_label_length(Some(4))),
+            Some(ParseError::BadLabelTooLong)
+        ));
+
+        let long = format!(
+            "# TYPE a gauge\na{{name=\"{}\"}} 1\n# EOF\n",
+            "x".repeat(200)
+        );
+        let options = ParserOptions::new().naive_wide_char_support(false);
+        assert!(parse_error(&long, &options).is_some());
+        assert!(parse_error(&long, &options.max_label_length(None)).is_none());
+    }
+
+    #[test]
+    fn format() {
+        let data = "a 1\n";
+        assert!(parse_with_options(data, &ParserOptions::new()).is_err());
+        let set = parse_with_options(data, &ParserOptions::new().format(Format::Auto))
+            .expect("couldn't parse");
+        assert_eq!(1, set.len());
+    }
+}
+
+mod lenient {
+    use crate::*;
+    use indoc::indoc;
+    use parser::{ParseError, ParserOptions};
+
+    /// The rule and line of each diagnostic, lex errors have no rule
+    fn diagnostics(errors: Vec<OmError>) -> Vec<(Option<ParseError>, usize)> {
+        errors
+            .into_iter()
+            .map(|err| match err {
+                OmError::Parse { error, location } => (Some(error), location.line),
+                OmError::LexError { location, .. } => (None, location.line),
+                err => panic!("unexpected error: {}", err),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_good_families() {
+        let data = indoc! {r#"
+            # TYPE a gauge
+            a 1
+            # TYPE b counter
+            b_total -1
+            # TYPE c gauge
+            c 1
+            # EOF
+        "#};
+
+        let (set, errors) = parse_lenient(data, &ParserOptions::new());
+        assert_eq!(vec!["a", "c"], set.names().collect::<Vec<_>>());
+        assert_eq!(vec![(Some(ParseError::BadCounter), 4)], diagnostics(errors));
+
+        // The strict parser agrees on the first error
+        assert!(parse_with_options(data, &ParserOptions::new()).is_err());
+    }
+
+    #[test]
+    fn skips_bad_lines() {
+        let data = indoc! {r#"
+            # TYPE a gauge
+            a 1
+            a{ 2
+            # TYPE b gauge
+            b 1
+            # EOF
+            c 1
+        "#};
+
+        let (set, errors) = parse_lenient(data, &ParserOptions::new());
+        assert_eq!(vec!["a", "b"], set.names().collect::<Vec<_>>());
+        assert_eq!(1, set["a"].samples.len());
+        assert_eq!(
+            vec![(None, 3), (Some(ParseError::Eof), 7)],
+            diagnostics(errors)
+        );
+    }
+
+    #[test]
+    fn interleaved_families() {
+        let data = indoc! {r#"
+            # TYPE a gauge
+            a 1
+            # TYPE b gauge
+            b 1
+            # TYPE a gauge
+            a 2
+            # EOF
+        "#};
+
+        let (set, errors) = parse_lenient(data, &ParserOptions::new());
+        assert_eq!(vec!["a", "b"], set.names().collect::<Vec<_>>());
+        assert!(set["a"].samples[0].number == 1.);
+        assert_eq!("# TYPE a gauge", errors[0].location().unwrap().text);
+        assert_eq!(vec![(Some(ParseError::Interleave), 5)], diagnostics(errors));
+    }
+
+    #[test]
+    fn missing_eof() {
+        let (set, errors) = parse_lenient("# TYPE a gauge\na 1\n", &ParserOptions::new());
+        assert_eq!(1, set.len());
+        assert_eq!(vec![(Some(ParseError::MissingEof), 2)], diagnostics(errors));
+    }
+
+    #[test]
+    fn prometheus() {
+        let data = indoc! {r#"
+            # TYPE a histogram
+            a_bucket{le="1"} 0
+            a_count 0
+            # TYPE b gauge
+            b 1
+            c{ 1
+            d 1
+        "#};
+
+        let options = ParserOptions::new().format(Format::Prometheus);
+        let (set, errors) = parse_lenient(data, &options);
+        assert_eq!(vec!["b", "d"], set.names().collect::<Vec<_>>());
+        assert_eq!(
+            vec![(Some(ParseError::BadHistogram), 3), (None, 6)],
+            diagnostics(errors)
+        );
+    }
+
+    #[test]
+    fn valid_expositions() {
+        let data = indoc! {r#"
+            # TYPE a histogram
+            a_bucket{le="+Inf"} 1
+            a_count 1
+            a_sum 1
+            # TYPE b info
+            b_info{b="x"} 1
+            # EOF
+        "#};
+
+        let (set, errors) = parse_lenient(data, &ParserOptions::new());
+        assert!(errors.is_empty());
+        let strict = parse_with_options(data, &ParserOptions::new()).expect("couldn't parse");
+        assert_eq!(serialize_metric_set(&strict), serialize_metric_set(&set));
+    }
+
+    #[test]
+    fn rule_ids() {
+        let data = indoc! {r#"
+            # TYPE a gauge
+            a{ 1
+            # TYPE b counter
+            b_total -1
+            # TYPE c summary
+            c{le="1"} 1
+            # EOF
+        "#};
+
+        let (_, errors) = parse_lenient(data, &ParserOptions::new());
+        let rules: Vec<_> = errors.iter().map(OmError::rule_id).collect();
+        assert_eq!(vec!["syntax", "bad-counter", "summary-with-le"], rules);
+        assert_eq!("malformed «Counter» MetricFamily", errors[1].message());
+    }
+}
+
+mod created {
+    use super::parse_error;
+    use crate::*;
+    use indoc::indoc;
+    use parser::{ParseError, ParserOptions, SampleKind};
+
+    #[test]
+    fn classified() {
+        let families = parse(indoc! {r#"
+            # TYPE a counter
+            a_total{x="1"} 1
+            a_created{x="1"} 1000.5
+            a_total{x="2"} 1
+            # TYPE b_created gauge
+            b_created 1
+            # EOF
+        "#})
+        .expect("couldn't parse");
+
+        assert_eq!(SampleKind::Created, families["a"].samples[1].kind);
+        assert_eq!(SampleKind::Other, families["b_created"].samples[0].kind);
+
+        let counters = families["a"].as_counters().expect("not a counter");
+        assert_eq!(
+            vec![Some(1000.5), None],
+            counters.iter().map(|c| c.created).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn numeric() {
+        let data = indoc! {r#"
+            # TYPE a counter
+            a_total 1
+            a_created NaN
+            # EOF
+        "#};
+        assert_eq!(
+            Some(ParseError::BadCreated),
+            parse_error(data, &ParserOptions::new())
+        );
+
+        // Only a created timestamp has to be a number
+        for data in [
+            "# TYPE a_created gauge\na_created NaN\n# EOF\n",
+            "# TYPE a_created unknown\na_created NaN\n# EOF\n",
+            "a_created NaN\n# EOF\n",
+        ] {
+            assert_eq!(None, parse_error(data, &ParserOptions::new()), "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn one_per_labelset() {
+        let data = indoc! {r#"
+            # TYPE a summary
+            a_count{x="1"} 1
+            a_sum{x="1"} 1
+            a_created{x="1"} 1
+            a_created{x="1"} 2
+            # EOF
+        "#};
+        assert_eq!(
+            Some(ParseError::BadCreated),
+            parse_error(data, &ParserOptions::new())
+        );
+
+        // Each MetricPoint may have its own
+        let data = indoc! {r#"
+            # TYPE a counter
+            a_total 1 1
+            a_created 1 1
+            a_total 2 2
+            a_created 1 2
+            # EOF
+        "#};
+        assert_eq!(None, parse_error(data, &ParserOptions::new()));
+    }
+
+    #[test]
+    fn allowed_types() {
+        for metric_type in ["gauge", "gaugehistogram", "unknown"] {
+            let data = format!("# TYPE a {}\na_created 1\n# EOF\n", metric_type);
+            assert_eq!(
+                Some(ParseError::BadCreated),
+                parse_error(&data, &ParserOptions::new()),
+                "{}",
+                metric_type
+            );
+        }
+    }
+}
+
+mod units {
+    use super::parse_error;
+    use crate::*;
+    use parser::{ParseError, ParserOptions};
+
+    #[test]
+    fn bad_units() {
+        for data in [
+            "# UNIT a\n# EOF\n",
+            "# UNIT a\t\n# EOF\n",
+            "# UNIT a seconds\n# EOF\n",
+            "# UNIT a_seconds seconds \n# EOF\n",
+            "# UNIT a_seconds sec-onds\n# EOF\n",
+            "# UNIT a_seconds a_seconds\n# EOF\n",
+            // Even where the metric name isn't, a unit is ASCII
+            "# UNIT a_µs µs\n# EOF\n",
+            "# TYPE x_u info\n# UNIT x_u u\n# EOF\n",
+            "# UNIT x_u u\n# TYPE x_u stateset\n# EOF\n",
+        ] {
+            assert_eq!(
+                Some(ParseError::BadUnit),
+                parse_error(data, &ParserOptions::new()),
+                "{:?}",
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn good_units() {
+        for data in [
+            "# UNIT a \n# EOF\n",
+            "# TYPE a_seconds gauge\n# UNIT a_seconds seconds\n# EOF\n",
+            "# UNIT a_kilo_bytes kilo_bytes\n# EOF\n",
+            "# TYPE x info\n# UNIT x \n# EOF\n",
+        ] {
+            assert_eq!(None, parse_error(data, &ParserOptions::new()), "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn empty_unit_roundtrip() {
+        let families = parse("# TYPE a gauge\n# UNIT a \na 1\n# EOF\n").expect("couldn't parse");
+        assert_eq!(Some(""), families["a"].unit.as_deref());
+
+        let exposition = serialize(&families);
+        let reparsed = parse(&exposition).expect("couldn't parse serialized exposition");
+        assert_eq!(Some(""), reparsed["a"].unit.as_deref());
+    }
+}
+
+mod summaries {
+    use super::parse_error;
+    use crate::*;
+    use indoc::indoc;
+    use parser::{ParseError, ParserOptions};
+
+    #[test]
+    fn duplicate_quantile() {
+        let data = indoc! {r#"
+            # TYPE a summary
+            a{x="1",quantile="0.5"} 1
+            a{x="1",quantile="0.50"} 2
+            a{x="2",quantile="0.5"} 1
+            # EOF
+        "#};
+        assert_eq!(
+            Some(ParseError::DuplicateQuantile),
+            parse_error(data, &ParserOptions::new())
+        );
+
+        // Not mistaken for a series going back in time
+        let data = "# TYPE s summary\ns{quantile=\"0.5\"} 1\ns{quantile=\"0.5\"} 2\n# EOF\n";
+        assert_eq!(
+            Some(ParseError::DuplicateQuantile),
+            parse_error(data, &ParserOptions::new())
+        );
+
+        // The same quantile at another time is another MetricPoint
+        let data = indoc! {r#"
+            # TYPE a summary
+            a{quantile="0.5"} 1 1
+            a{quantile="0.5"} 2 2
+            # EOF
+        "#};
+        assert_eq!(None, parse_error(data, &ParserOptions::new()));
+    }
+
+    #[test]
+    fn count_sum_pairing() {
+        let data = indoc! {r#"
+            # TYPE a summary
+            a_count{x="1"} 1
+            a_sum{x="1"} 2
+            a_count{x="2"} 1
+            # EOF
+        "#};
+        assert_eq!(
+            Some(ParseError::UnpairedSummaryCountSum),
+            parse_error(data, &ParserOptions::new())
+        );
+
+        let data = indoc! {r#"
+            # TYPE a summary
+            a{quantile="0.5"} 1
+            a_sum 2
+            # EOF
+        "#};
+        assert_eq!(
+            Some(ParseError::UnpairedSummaryCountSum),
+            parse_error(data, &ParserOptions::new())
+        );
+    }
+
+    #[test]
+    fn negative_values() {
+        for count in ["-1", "NaN"] {
+            let data = format!("# TYPE a summary\na_count {}\na_sum 1\n# EOF\n", count);
+            assert_eq!(
+                Some(ParseError::BadSummaryCount),
+                parse_error(&data, &ParserOptions::new()),
+                "{}",
+                count
+            );
+        }
+
+        for sum in ["-1", "NaN"] {
+            let data = format!("# TYPE a summary\na_count 1\na_sum {}\n# EOF\n", sum);
+            assert_eq!(
+                Some(ParseError::BadSummarySum),
+                parse_error(&data, &ParserOptions::new()),
+                "{}",
+                sum
+            );
+        }
+
+        let data = "# TYPE a summary\na{quantile=\"0.5\"} -1\n# EOF\n";
+        assert_eq!(
+            Some(ParseError::BadQuantileValue),
+            parse_error(data, &ParserOptions::new())
+        );
+
+        // A histogram's count is still reported as such
+        let data = "# TYPE a histogram\na_bucket{le=\"+Inf\"} 1\na_count -1\na_sum 1\n# EOF\n";
+        assert_eq!(
+            Some(ParseError::BadHistogram),
+            parse_error(data, &ParserOptions::new())
+        );
+    }
+
+    #[test]
+    fn quantile_with_le() {
+        for sample in [r#"a{quantile="0.5",le="1"} 1"#, r#"a_count{le="1"} 1"#] {
+            let data = format!("# TYPE a summary\n{}\n# EOF\n", sample);
+            assert_eq!(
+                Some(ParseError::SummaryWithLe),
+                parse_error(&data, &ParserOptions::new()),
+                "{}",
+                sample
+            );
+        }
+    }
+
+    #[test]
+    fn sorted_quantiles() {
+        let families = parse(indoc! {r#"
+            # TYPE a summary
+            a{quantile="0.9"} 3
+            a{quantile="0.1"} 1
+            a{quantile="0.5"} 2
+            # EOF
+        "#})
+        .expect("couldn't parse");
+
+        let summaries = families["a"].as_summaries().expect("not a summary");
+        assert_eq!(
+            vec![0.1, 0.5, 0.9],
+            summaries[0]
+                .quantiles
+                .iter()
+                .map(|q| q.quantile)
+                .collect::<Vec<_>>()
+        );
+    }
+}
+
+mod gaugehistograms {
+    use super::parse_error;
+    use crate::*;
+    use indoc::indoc;
+    use parser::{ParseError, ParserOptions};
+
+    #[test]
+    fn per_labelset() {
+        let data = indoc! {r#"
+            # TYPE a gaugehistogram
+            a_bucket{x="1",le="1"} 5
+            a_bucket{x="1",le="+Inf"} 5
+            a_gcount{x="1"} 5
+            a_gsum{x="1"} 2
+            a_bucket{x="2",le="1"} 1
+            a_bucket{x="2",le="+Inf"} 2
+            a_gcount{x="2"} 2
+            a_gsum{x="2"} 2
+            # EOF
+        "#};
+        assert_eq!(None, parse_error(data, &ParserOptions::new()));
+
+        let data = indoc! {r#"
+            # TYPE a gaugehistogram
+            a_bucket{x="1",le="+Inf"} 5
+            a_bucket{x="2",le="1"} 1
+            # EOF
+        "#};
+        assert_eq!(
+            Some(ParseError::BadHistogram),
+            parse_error(data, &ParserOptions::new())
+        );
+    }
+
+    #[test]
+    fn gcount_gsum_pairing() {
+        // Each series has one of the two, but neither has both
+        let data = indoc! {r#"
+            # TYPE g gaugehistogram
+            g_bucket{a="1",le="+Inf"} 1
+            g_gcount{a="1"} 1
+            g_bucket{a="2",le="+Inf"} 1
+            g_gsum{a="2"} 1
+            # EOF
+        "#};
+        assert_eq!(
+            Some(ParseError::BadHistogram),
+            parse_error(data, &ParserOptions::new())
+        );
+    }
+
+    #[test]
+    fn negative_gsum() {
+        // Only the series with a negative bucket may have a negative gsum
+        let data = indoc! {r#"
+            # TYPE g gaugehistogram
+            g_bucket{a="1",le="-1"} 1
+            g_bucket{a="1",le="+Inf"} 1
+            g_gcount{a="1"} 1
+            g_gsum{a="1"} -1
+            g_bucket{a="2",le="+Inf"} 1
+            g_gcount{a="2"} 1
+            g_gsum{a="2"} -1
+            # EOF
+        "#};
+        assert_eq!(
+            Some(ParseError::BadCounter),
+            parse_error(data, &ParserOptions::new())
+        );
+        assert_eq!(
+            None,
+            parse_error(
+                &data.replace("g_gsum{a=\"2\"} -1", "g_gsum{a=\"2\"} 1"),
+                &ParserOptions::new()
+            )
+        );
+    }
+
+    #[test]
+    fn bucket_order() {
+        let data = indoc! {r#"
+            # TYPE a gaugehistogram
+            a_bucket{le="2"} 0
+            a_bucket{le="1"} 0
+            a_bucket{le="+Inf"} 0
+            # EOF
+        "#};
+
+        let options = ParserOptions::new();
+        assert_eq!(
+            Some(ParseError::BadBucketOrder),
+            parse_error(data, &options)
+        );
+        assert_eq!(
+            None,
+            parse_error(data, &options.enforce_bucket_order(false))
+        );
+    }
+
+    #[test]
+    fn cumulative() {
+        let data = indoc! {r#"
+            # TYPE a gaugehistogram
+            a_bucket{le="1"} 2
+            a_bucket{le="2"} 1
+            a_bucket{le="+Inf"} 2
+            # EOF
+        "#};
+        assert_eq!(
+            Some(ParseError::DecreasingBucket),
+            parse_error(data, &ParserOptions::new())
+        );
+    }
+
+    #[test]
+    fn gcount() {
+        let data = indoc! {r#"
+            # TYPE a gaugehistogram
+            a_bucket{le="1"} 1
+            a_bucket{le="+Inf"} 2
+            a_gcount 3
+            a_gsum 1
+            # EOF
+        "#};
+
+        let options = ParserOptions::new().validate_histogram_count(true);
+        assert_eq!(
+            Some(ParseError::BadHistogramCount),
+            parse_error(data, &options)
+        );
+        assert_eq!(
+            None,
+            parse_error(data, &options.validate_histogram_count(false))
+        );
+    }
+}
+
+mod histograms {
+    use super::parse_error;
+    use crate::*;
+    use indoc::indoc;
+    use parser::{ParseError, ParserOptions};
+
+    fn counted() -> ParserOptions {
+        ParserOptions::new().validate_histogram_count(true)
+    }
+
+    #[test]
+    fn multiple_labelsets() {
+        let data = indoc! {r#"
+            # TYPE a histogram
+            a_bucket{method="GET",le="0.5"} 10
+            a_bucket{method="GET",le="1"} 20
+            a_bucket{method="GET",le="+Inf"} 25
+            a_count{method="GET"} 25
+            a_sum{method="GET"} 12
+            a_bucket{method="POST",le="0.5"} 1
+            a_bucket{method="POST",le="1"} 2
+            a_bucket{method="POST",le="+Inf"} 3
+            a_count{method="POST"} 3
+            a_sum{method="POST"} 1
+            # EOF
+        "#};
+        assert_eq!(None, parse_error(data, &counted()));
+
+        let histograms = parse(data).unwrap()["a"].as_histograms().unwrap().len();
+        assert_eq!(2, histograms);
+    }
+
+    #[test]
+    fn per_labelset_errors() {
+        // Each series is checked on its own, so one bad series is enough
+        let cases = [
+            (ParseError::BadBucketOrder, r#"a_bucket{x="2",le="0.5"} 1"#),
+            (ParseError::DecreasingBucket, r#"a_bucket{x="2",le="2"} 0"#),
+            (
+                ParseError::BadHistogramCount,
+                "a_count{x=\"2\"} 1\na_sum{x=\"2\"} 1",
+            ),
+        ];
+
+        for (error, line) in cases {
+            let data = format!(
+                "# TYPE a histogram\n\
+                 a_bucket{{x=\"1\",le=\"+Inf\"}} 5\n\
+                 a_bucket{{x=\"2\",le=\"1\"}} 1\n\
+                 {}\n\
+                 a_bucket{{x=\"2\",le=\"+Inf\"}} 2\n\
+                 # EOF\n",
+                line
+            );
+            assert_eq!(Some(error), parse_error(&data, &counted()), "{}", line);
+        }
+    }
+
+    #[test]
+    fn sum_and_count_per_labelset() {
+        let data = indoc! {r#"
+            # TYPE a histogram
+            a_bucket{x="1",le="+Inf"} 1
+            a_count{x="1"} 1
+            a_sum{x="1"} 1
+            a_bucket{x="2",le="+Inf"} 1
+            a_count{x="2"} 1
+            # EOF
+        "#};
+        assert_eq!(
+            Some(ParseError::BadHistogram),
+            parse_error(data, &counted())
+        );
+
+        // Negative buckets only rule out the sum of their own series
+        let data = indoc! {r#"
+            # TYPE a histogram
+            a_bucket{x="1",le="-1"} 0
+            a_bucket{x="1",le="+Inf"} 1
+            a_bucket{x="2",le="+Inf"} 1
+            a_count{x="2"} 1
+            a_sum{x="2"} 1
+            # EOF
+        "#};
+        assert_eq!(None, parse_error(data, &counted()));
+    }
+}
+
+mod bytes {
+    use crate::*;
+    use indoc::indoc;
+
+    /// Lexes `data` with both lexers, checking they agree
+    fn agree(data: &str) -> bool {
+        let nom = lexer::exposition(data);
+        let bytes = lexer::bytes::exposition(data.as_bytes());
+        assert_eq!(
+            super::lexed_json(&nom),
+            super::lexed_json(&bytes),
+            "{:?}",
+            data
+        );
+        bytes.is_ok()
+    }
+
+    #[test]
+    fn same_tokens() {
+        assert!(agree(indoc! {r#"
+            # TYPE a histogram
+            # HELP a help with a \" quote and a \n newline
+            # UNIT a
+            a_bucket{le="1.5",b="c\"d\\"} 3 1.5 # {trace_id="abc"} 0.5 1.25
+            a_bucket{le="+Inf"} 4 # {} NaN
+            a_count 4
+            a_sum 1e-3
+            # TYPE b gauge
+            b{} -Inf
+            b{x=""} +12345678901234567890
+            b{x="é"} 10
+            # EOF
+        "#}));
+    }
+
+    #[test]
+    fn same_quirks() {
+        for line in [
+            r#"# HELP a text "with" a quote"#,
+            r#"# HELP a trailing \"#,
+            "# HELP a",
+            "# HELP a ",
+            "# UNIT a!",
+            "# UNIT a seconds ",
+            "# TYPE a gauge ",
+            "# TYPE a gauges",
+            "# EOF trailing",
+            "a 1  2",
+            "a 1 #",
+            "a 1 # {a=\"b\"}",
+            "a 1 # {a=\"b\"} 1 2 3",
+            r#"a{b="c",} 1"#,
+            r#"a{b="c\"} 1"#,
+            "a{b=c} 1",
+            "a 0x1",
+            "a 1e",
+            "a inf",
+            "a 1 NaN",
+            "a 1 infinity",
+            "ä 1",
+            "aä 1",
+            "a{ä=\"b\"} 1",
+            "",
+            " a 1",
+        ] {
+            agree(&format!("{}\n# EOF", line));
+        }
+    }
+
+    #[test]
+    fn last_line() {
+        assert!(agree("# EOF"));
+        assert!(agree("# EOF\n"));
+        assert!(!agree("# EOF\n\n"));
+        assert!(!agree("a 1"));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let err = lexer::bytes::exposition(b"a 1\nb{c=\"\xff\"} 1\n# EOF\n").expect_err("invalid");
+        let location = err.location().expect("has a location");
+        assert_eq!((2, 6), (location.line, location.column));
+    }
+
+    #[test]
+    fn inline_labels() {
+        let lines = lexer::bytes::exposition(b"a{b=\"1\",c=\"2\"} 1\n# EOF\n").expect("should lex");
+        match &lines[0].token {
+            lexer::MetricToken::Metric(sample) => {
+                assert!(!sample.labels.as_ref().expect("has labels").spilled())
+            }
+            token => panic!("not a sample: {:?}", token),
+        }
+    }
+
+    #[test]
+    fn parse_bytes() {
+        let data = indoc! {r#"
+            # TYPE a counter
+            a_total{b="c"} 1
+            # EOF
+        "#};
+        assert_eq!(
+            serialize_metric_set(&crate::parse_metric_set(data).expect("should parse")),
+            serialize_metric_set(&crate::parse_bytes(data.as_bytes()).expect("should parse")),
+        );
+    }
+}
+
+mod parallel {
+    use crate::*;
+    use indoc::indoc;
+
+    use super::parsed_or_error;
+
+    /// Parses `data` sequentially and on each number of threads, checking they agree
+    fn agree(data: &str) -> bool {
+        let sequential = crate::parse_metric_set(data);
+        for threads in 0..5 {
+            let parallel = parser::parse_metric_set_parallel(
+                data.as_bytes(),
+                threads,
+                &parser::ParserOptions::new(),
+            );
+            assert_eq!(
+                parsed_or_error(&sequential),
+                parsed_or_error(&parallel),
+                "{}",
+                threads
+            );
+        }
+        sequential.is_ok()
+    }
+
+    /// An exposition with a family for each of `range`
+    fn families(range: std::ops::Range<usize>) -> String {
+        range
+            .map(|i| {
+                format!(
+                    "# TYPE f{} counter\n# HELP f{} help\nf{}_total{{a=\"{}\"}} {}\n",
+                    i, i, i, i, i
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_families() {
+        let data = families(0..100) + "# EOF\n";
+        assert!(agree(&data));
+
+        let metric_set =
+            parser::parse_metric_set_parallel(data.as_bytes(), 4, &parser::ParserOptions::new())
+                .expect("should parse");
+        assert_eq!(100, metric_set.len());
+        assert_eq!(Some("f99"), metric_set.names().last());
+        assert_eq!(298, metric_set["f99"].line);
+    }
+
+    #[test]
+    fn untyped_families() {
+        assert!(agree(indoc! {r#"
+            a 1
+            a{b="c"} 2
+            # TYPE b gauge
+            b 1
+            c 1
+            # HELP d help
+            d 1
+            # EOF
+        "#}));
+        assert!(agree("# EOF\n"));
+        assert!(agree("# EOF"));
+    }
+
+    #[test]
+    fn name_conflicts() {
+        // Only a family named after one already seen is checked
+        agree(&(families(0..10) + "# TYPE f3_total gauge\nf3_total 1\n# EOF\n"));
+
+        let data =
+            String::from("# TYPE f3_total gauge\nf3_total 1\n") + &families(0..10) + "# EOF\n";
+        assert!(!agree(&data));
+    }
+
+    #[test]
+    fn duplicate_families() {
+        assert!(!agree(
+            &(families(0..10) + "# TYPE f5 counter\nf5_total 1\n# EOF\n")
+        ));
+        assert!(!agree(&(families(0..10) + &families(0..10) + "# EOF\n")));
+    }
+
+    #[test]
+    fn misplaced_eof() {
+        assert!(!agree(&families(0..10)));
+        assert!(!agree(
+            &(families(0..10) + "# EOF\n" + &families(10..20) + "# EOF\n")
+        ));
+        assert!(!agree(&(families(0..10) + "# EOF\n\n")));
+    }
+
+    #[test]
+    fn family_errors() {
+        // A bad family, a lexer error, and both
+        assert!(!agree(
+            &(families(0..10) + "# TYPE g counter\ng_total -1\n" + &families(10..20) + "# EOF\n")
+        ));
+        assert!(!agree(&(families(0..10) + "g{ 1\n" + "# EOF\n")));
+        assert!(!agree(
+            &(families(0..10)
+                + "# TYPE g counter\ng_total -1\n"
+                + &families(10..20)
+                + "h{ 1\n# EOF\n")
+        ));
+    }
+
+    #[test]
+    fn error_order() {
+        // The name conflict is found before the histogram's missing +Inf bucket
+        assert!(!agree(
+            &(families(0..10) + "# TYPE f3_total histogram\nf3_total_bucket{le=\"1\"} 1\n# EOF\n")
+        ));
+        // Past the EOF the family it's in isn't completed, nor is a last family without one
+        let bad = "# TYPE g histogram\ng_bucket{le=\"1\"} 1\n";
+        assert!(!agree(
+            &(families(0..10) + bad + "# EOF\n" + &families(10..20) + "# EOF\n")
+        ));
+        assert!(!agree(&(families(0..10) + bad)));
+    }
+
+    #[test]
+    fn prometheus_format() {
+        let err = parser::parse_metric_set_parallel(
+            b"a 1\n",
+            2,
+            &parser::ParserOptions::new().format(Format::Prometheus),
+        )
+        .expect_err("should not parse");
+
+        assert!(matches!(
+            err,
+            OmError::UnsupportedFormat(Format::Prometheus)
+        ));
+    }
+}
+
+mod label_sets {
+    use crate::*;
+    use indoc::indoc;
+
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    use parser::LabelSet;
+
+    fn hash(labels: &LabelSet) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        labels.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn sorted_by_name() {
+        let labels: LabelSet = [("b", "2"), ("c", "3"), ("a", "1")].into_iter().collect();
+
+        assert_eq!(
+            vec![("a", "1"), ("b", "2"), ("c", "3")],
+            labels.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(Some("2"), labels.get("b"));
+        assert_eq!(None, labels.get("d"));
+        assert!(labels.contains_key("c"));
+        assert_eq!("3", labels["c"]);
+        assert_eq!(3, labels.len());
+    }
+
+    #[test]
+    fn identity() {
+        let a: LabelSet = [("x", "1"), ("y", "2")].into_iter().collect();
+        let b: LabelSet = [("y", "2"), ("x", "1")].into_iter().collect();
+        let c: LabelSet = [("x", "1"), ("y", "3")].into_iter().collect();
+
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+        assert_ne!(a, c);
+        assert!(a < c);
+        assert_eq!(a, a.clone());
+        assert_eq!(LabelSet::new(), LabelSet::default());
+
+        // A repeated name keeps its last value, as with a map
+        let d: LabelSet = [("y", "1"), ("x", "1"), ("y", "2")].into_iter().collect();
+        assert_eq!(a, d);
+    }
+
+    #[test]
+    fn display() {
+        let labels: LabelSet = [("path", r#"C:\"x"\y"#), ("a", "line\nbreak")]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            r#"{a="line\nbreak",path="C:\\\"x\"\\y"}"#,
+            labels.to_string()
+        );
+        assert_eq!("{}", LabelSet::new().to_string());
+    }
+
+    #[test]
+    fn serde() {
+        let labels: LabelSet = [("b", "2"), ("a", "1")].into_iter().collect();
+        let json = serde_json::to_string(&labels).expect("couldn't serialize");
+        assert_eq!(r#"{"a":"1","b":"2"}"#, json);
+        assert_eq!(
+            labels,
+            serde_json::from_str::<LabelSet>(&json).expect("couldn't deserialize")
+        );
+    }
+
+    #[test]
+    fn parsed_samples() {
+        let set = crate::parse_metric_set(indoc! {r#"
+            # TYPE a counter
+            a_total{z="1",b="2"} 1 # {trace_id="abc",span_id="def"} 1
+            a_total{b="2",z="2"} 1
+            # EOF
+        "#})
+        .expect("should parse");
+
+        let samples = &set["a"].samples;
+        let names: Vec<_> = samples[0].labels.iter().map(|(name, _)| name).collect();
+        assert_eq!(vec!["b", "z"], names);
+        assert!(samples[0].labels < samples[1].labels);
+        assert_eq!(
+            r#"{span_id="def",trace_id="abc"}"#,
+            samples[0].exemplar.as_ref().unwrap().labels.to_string()
+        );
+
+        let err = crate::parse_metric_set("a{b=\"1\",b=\"2\"} 1\n# EOF\n").expect_err("duplicate");
+        assert!(matches!(
+            err,
+            OmError::Parse {
+                error: parser::ParseError::DuplicateMeta,
+                ..
+            }
+        ));
+    }
+}
+
+mod series {
+    use crate::*;
+    use indoc::indoc;
+    use parser::LabelSet;
+
+    #[test]
+    fn histogram_points() {
+        let set = parse_metric_set(indoc! {r#"
+            # TYPE a histogram
+            a_bucket{x="1",le="1"} 0 1
+            a_bucket{x="1",le="+Inf"} 2 1
+            a_count{x="1"} 2 1
+            a_sum{x="1"} 3 1
+            a_created{x="1"} 0 1
+            a_bucket{x="1",le="1"} 1 2
+            a_bucket{x="1",le="+Inf"} 3 2
+            a_bucket{x="2",le="+Inf"} 1
+            # EOF
+        "#})
+        .expect("should parse");
+        let family = &set["a"];
+
+        let points = family.points();
+        assert_eq!(3, points.len());
+
+        let x1: LabelSet = [("x", "1")].into_iter().collect();
+        let point = &points[0];
+        assert_eq!(x1, point.labels);
+        assert_eq!(Some(1.), point.timestamp);
+        let bounds: Vec<_> = point.buckets().map(|(le, _)| le).collect();
+        assert_eq!(vec![1., f64::INFINITY], bounds);
+        assert_eq!(point.count().unwrap().number, 2.);
+        assert_eq!(point.sum().unwrap().number, 3.);
+        assert!(point.created().is_some());
+        assert!(point.total().is_none());
+        assert!(points[1].count().is_none());
+
+        let series = family.series();
+        assert_eq!(2, series.len());
+        assert_eq!(x1, series[0].labels);
+        assert_eq!(2, series[0].points.len());
+        assert_eq!(1, series[1].points.len());
+        assert_eq!(Some("2"), series[1].labels.get("x"));
+
+        for sample in &family.samples {
+            assert!(!family.series_labels(sample).contains_key("le"));
+        }
+    }
+
+    #[test]
+    fn point_labels() {
+        let set = parse_metric_set(indoc! {r#"
+            # TYPE s summary
+            s{quantile="0.5"} 1
+            s{quantile="0.9"} 2
+            s_count 3
+            s_sum 4
+            # TYPE st stateset
+            st{st="a",x="1"} 1
+            st{st="b",x="1"} 0
+            # TYPE c counter
+            c_total{le="1"} 1
+            c_total{le="2"} 1
+            # EOF
+        "#})
+        .expect("should parse");
+
+        let points = set["s"].points();
+        assert_eq!(1, points.len());
+        assert!(points[0].labels.is_empty());
+        let quantiles: Vec<_> = points[0].quantiles().map(|(q, _)| q).collect();
+        assert_eq!(vec![0.5, 0.9], quantiles);
+
+        let points = set["st"].points();
+        assert_eq!(1, points.len());
+        assert_eq!(2, points[0].samples.len());
+        assert_eq!(Some("1"), points[0].labels.get("x"));
+        assert!(!points[0].labels.contains_key("st"));
+
+        // Only histograms tell their samples apart by `le`
+        let points = set["c"].points();
+        assert_eq!(2, points.len());
+        assert!(points.iter().all(|point| point.total().is_some()));
+    }
+
+    #[test]
+    fn without() {
+        let labels: LabelSet = [("a", "1"), ("le", "2")].into_iter().collect();
+        let expected: LabelSet = [("a", "1")].into_iter().collect();
+        assert_eq!(expected, labels.without("le"));
+        assert_eq!(labels, labels.without("b"));
+    }
+}
+
+mod exemplars {
+    use crate::*;
+    use indoc::indoc;
+
+    #[test]
+    fn trace_ids() {
+        let set = parse_metric_set(indoc! {r#"
+            # TYPE a histogram
+            a_bucket{le="1"} 0
+            a_bucket{le="+Inf"} 1 # {trace_id="4bf92f35",span_id="00f067aa"} 2 1.5
+            # TYPE b counter
+            b_total 1 # {user="x"} 1
+            # EOF
+        "#})
+        .expect("should parse");
+
+        let exemplars: Vec<_> = set["a"].points()[0]
+            .buckets()
+            .filter_map(|(_, sample)| sample.exemplar.as_ref())
+            .collect();
+        assert_eq!(1, exemplars.len());
+        assert_eq!(Some("4bf92f35"), exemplars[0].trace_id());
+        assert_eq!(Some("00f067aa"), exemplars[0].span_id());
+        assert_eq!(2., exemplars[0].number);
+        assert_eq!(Some(1.5), exemplars[0].timestamp);
+
+        let exemplar = set["b"].samples[0].exemplar.as_ref().unwrap();
+        assert_eq!(None, exemplar.trace_id());
+        assert_eq!(Some("x"), exemplar.labels.get("user"));
+    }
+}
+
+mod query {
+    use crate::query::*;
+    use crate::*;
+    use indoc::indoc;
+
+    fn selected(selector: &str) -> Vec<String> {
+        let set = parse_metric_set(indoc! {r#"
+            # TYPE http_requests counter
+            http_requests_total{method="GET",code="200"} 10
+            http_requests_created{method="GET",code="200"} 1
+            http_requests_total{method="POST",code="500"} 2
+            http_requests_total{method="PUT",code="200"} 3
+            # TYPE go_goroutines gauge
+            go_goroutines 7
+            # EOF
+        "#})
+        .expect("should parse");
+        let selector: Selector = selector.parse().expect("should parse");
+
+        selector
+            .select(&set)
+            .map(|(_, sample)| format!("{}{} {}", sample.name, sample.labels, sample.number))
+            .collect()
+    }
+
+    #[test]
+    fn matchers() {
+        assert_eq!(
+            vec![r#"http_requests_total{code="200",method="GET"} 10"#],
+            selected(r#"http_requests_total{method=~"GET|POST",code!="500"}"#)
+        );
+        assert_eq!(
+            vec![r#"http_requests_total{code="500",method="POST"} 2"#],
+            selected(r#"{method!~"G.*|PUT", code = "500",}"#)
+        );
+        // Regexes match whole values
+        assert!(selected(r#"{method=~"G"}"#).is_empty());
+        // A missing label is an empty one
+        assert_eq!(vec!["go_goroutines{} 7"], selected(r#"{code=""}"#));
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(3, selected("http_requests_total").len());
+        assert_eq!(4, selected("http_requests").len());
+        assert_eq!(1, selected(r#"{__name__=~"go_.*"}"#).len());
+        assert_eq!(1, selected(r#"{__name__!="http_requests"}"#).len());
+        assert_eq!(
+            vec![r#"http_requests_created{code="200",method="GET"} 1"#],
+            selected(r#"{__name__=~".*_created"}"#)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            Selector::parse(r#"a{b="c""#),
+            Err(SelectorError::Syntax { column: 8, .. })
+        ));
+        assert!(matches!(
+            Selector::parse(r#"{b=~"("}"#),
+            Err(SelectorError::Regex { .. })
+        ));
+        assert!(matches!(Selector::parse("{}"), Err(SelectorError::Empty)));
+    }
+}